@@ -1,23 +1,104 @@
 use serde::{Serialize, Deserialize};
-use serde_yaml::from_reader;
+use serde_yaml::{from_reader, Value};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
 
+use crate::config;
+use crate::config::ImportOptions;
 use crate::db;
+use crate::ddl;
+use crate::check;
+use crate::foreign_keys;
+use crate::subset;
+use crate::filter;
+use crate::masking;
+use crate::masking::MaskingRule;
 use crate::utils;
 use std::borrow::Borrow;
 
+// A table can be given just by its name or with some extra options for it
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TableImport {
+    Name(String),
+    WithOptions(Box<TableWithOptions>)
+}
+
+#[derive(Serialize, Deserialize)]
+struct TableWithOptions {
+    name: String,
+    rename: Option<HashMap<String, String>>,
+    exclude: Option<Vec<String>>,
+    columns: Option<HashMap<String, Value>>,
+    partitions: Option<Vec<String>>,
+    include_children: Option<bool>,
+    chunk_key: Option<Vec<String>>,
+    sample_percent: Option<f64>,
+    sample_method: Option<String>,
+    seed: Option<i64>,
+    order_by: Option<String>,
+    limit: Option<i64>,
+    time_column: Option<String>
+}
+
+// Tables of a subset can be given with their schema (schema.table), or just by their name if they are in the schema of the job
+#[derive(Serialize, Deserialize)]
+struct SubsetRoot {
+    table: String,
+    where_clause: String
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubsetChild {
+    table: String,
+    // Max rows imported for each foreign key of the child table
+    limit: Option<i64>
+}
+
+// Rows selected in some root tables, along with the rows of other tables they need or that reference them
+#[derive(Serialize, Deserialize)]
+struct Subset {
+    roots: Vec<SubsetRoot>,
+    #[serde(default)]
+    children: Vec<SubsetChild>
+}
+
+// A job imports some tables of the schema, the result of a query into one table of the schema, or a subset of related tables
 #[derive(Serialize, Deserialize)]
 struct SchemaImport {
     schema: String,
-    tables: Vec<String>,
+    #[serde(default)]
+    tables: Vec<TableImport>,
+    query: Option<String>,
+    subset: Option<Subset>,
+    target_table: Option<String>,
+    // Other databases of the source and target servers, instead of the ones in the connection URLs
+    source_database: Option<String>,
+    target_database: Option<String>,
+    chunk_key: Option<Vec<String>>,
     where_clause: Option<String>,
     truncate: Option<bool>,
-    cascade: Option<bool>
+    cascade: Option<bool>,
+    replace: Option<bool>,
+    create_missing: Option<bool>,
+    add_missing_columns: Option<bool>,
+    // Import only the rows with the keys of this file in key_column
+    keys_file: Option<String>,
+    key_column: Option<String>,
+    // Import only the rows of a time window (since a period ago or a date, or between two dates) in the time_column of each table
+    time_column: Option<String>,
+    since: Option<String>,
+    between: Option<String>
 }
 
 #[derive(Serialize, Deserialize)]
 struct Batch {
+    // Filter applied to the tables of all jobs, instead of the one given by command line
+    filter_column: Option<String>,
+    tables_without_filter: Option<String>,
+    // Rules to mask the values of some columns of all jobs, instead of the ones of the masking file given by command line
+    masking: Option<Vec<MaskingRule>>,
     imports: Vec<SchemaImport>
 }
 
@@ -33,15 +114,52 @@ pub fn execute_batch_file(batch_file: &String) {
                     if !utils::check_postgres_source_target_servers() {
                         std::process::exit(1);
                     }
-                    
+
                     let batch:Batch = b;
+
+                    // Check all jobs, WHERE clauses and chosen partitions before importing anything
                     for (i, schema_import) in batch.imports.iter().enumerate() {
+                        check_job(i, schema_import);
+                    }
+                    let filter_column = batch.filter_column.to_owned().unwrap_or(config::CONFIG_PROPERTIES.filter_column.to_owned());
+                    let tables_without_filter = batch.tables_without_filter.to_owned()
+                        .unwrap_or(config::CONFIG_PROPERTIES.tables_without_filter.to_owned());
+                    let masking_rules = match &batch.masking {
+                        Some(rules) => { masking::check_rules(rules); rules.to_owned() },
+                        None => masking::rules_from_file(&config::CONFIG_PROPERTIES.masking_file)
+                    };
+                    let mut tables_of_jobs = vec!();
+                    for schema_import in batch.imports.iter() {
+                        use_databases_of(schema_import);
+                        if (schema_import.source_database.is_some() || schema_import.target_database.is_some())
+                            && !utils::check_server_settings() {
+                            std::process::exit(1);
+                        }
+                        let tables = filter::filter_by_column(tables_to_import_in(schema_import, &masking_rules), &filter_column, &tables_without_filter);
+                        let tables = filter::filter_by_keys_file(tables,
+                            schema_import.keys_file.as_ref().unwrap_or(&config::CONFIG_PROPERTIES.keys_file),
+                            schema_import.key_column.as_ref().unwrap_or(&config::CONFIG_PROPERTIES.key_column), &tables_without_filter);
+                        let (since, between) = time_window_of(schema_import);
+                        let tables = filter::filter_by_time_window(tables, &since, &between, &tables_without_filter);
+                        ddl::create_missing_objects(&tables);
+                        let tables = check::check_tables(tables);
+                        for (schema, table_name, table_options) in &tables {
+                            db::check_where_clause(schema, table_name, table_options);
+                            db::check_partitions(schema, table_name, table_options);
+                            db::check_chunk_key(schema, table_name, table_options);
+                            db::check_sample(schema, table_name, table_options);
+                        }
+                        tables_of_jobs.push(tables);
+                    }
+                    utils::exit_if_dry_run();
+
+                    for (i, (schema_import, tables)) in batch.imports.iter().zip(tables_of_jobs).enumerate() {
                         println!("====== Job {} ======", i);
-                        execute_schema_import(&schema_import.schema, &schema_import.tables, 
-                                              schema_import.where_clause
-                                                  .as_ref().unwrap_or(String::from("").borrow()),
-                                              schema_import.truncate.unwrap_or(false),
-                                              schema_import.cascade.unwrap_or(false));
+                        use_databases_of(schema_import);
+                        if schema_import.source_database.is_some() || schema_import.target_database.is_some() {
+                            println!("Importing from database {} to database {}", config::get_source_database(), config::get_target_database());
+                        }
+                        foreign_keys::import_tables(tables);
                     }
                 },
                 Err(err) => {
@@ -55,16 +173,148 @@ pub fn execute_batch_file(batch_file: &String) {
     }
 }
 
-fn execute_schema_import(schema:&String, tables:&Vec<String>, where_clause:&String, truncate:bool,
-    cascade: bool){
-    let mut checked_where_clause = &String::from("");
+fn use_databases_of(schema_import:&SchemaImport) {
+    config::use_databases(schema_import.source_database.to_owned(), schema_import.target_database.to_owned());
+}
+
+fn check_job(i:usize, schema_import:&SchemaImport) {
+    if let Some(subset) = &schema_import.subset {
+        if !schema_import.tables.is_empty() || schema_import.query.is_some() {
+            println!("Error in job {}: A job can import a subset, a query or some tables, but only one of them", i);
+            std::process::exit(1);
+        }
+        if subset.roots.is_empty() {
+            println!("Error in job {}: A subset needs some root tables with a where_clause to start from", i);
+            std::process::exit(1);
+        }
+        if schema_import.where_clause.is_some() {
+            println!("Error in job {}: The rows of a subset are chosen with the where_clause of its roots, not the one of the job", i);
+            std::process::exit(1);
+        }
+    }
+    else if schema_import.query.is_some() {
+        if schema_import.target_table.is_none() {
+            println!("Error in job {}: A query job needs a target_table to import the query into", i);
+            std::process::exit(1);
+        }
+        if !schema_import.tables.is_empty() {
+            println!("Error in job {}: A job can import a query or some tables, but not both", i);
+            std::process::exit(1);
+        }
+    }
+    else if schema_import.tables.is_empty() {
+        println!("Error in job {}: A job needs some tables or a query to import", i);
+        std::process::exit(1);
+    }
+}
+
+// All tables imported by the job (with the child tables of the ones including them), with the options to import each of them
+fn tables_to_import_in(schema_import:&SchemaImport, masking_rules:&[MaskingRule]) -> Vec<(String, String, ImportOptions)> {
+    let options = job_options_for(schema_import, masking_rules);
+
+    if let Some(subset) = &schema_import.subset {
+        let roots:Vec<((String, String), String)> = subset.roots.iter()
+            .map(|root| (table_in_schema(&root.table, &schema_import.schema), root.where_clause.to_owned()))
+            .collect();
+        let children:Vec<((String, String), Option<i64>)> = subset.children.iter()
+            .map(|child| (table_in_schema(&child.table, &schema_import.schema), child.limit))
+            .collect();
+        return subset::subset_tables(&roots, &children, &options);
+    }
+
+    if let Some(query) = &schema_import.query {
+        let query_options = ImportOptions { query: query.to_owned(), chunk_key: schema_import.chunk_key.to_owned().unwrap_or_default(),
+            ..options };
+        return vec!((schema_import.schema.to_owned(), schema_import.target_table.to_owned().unwrap_or_default(), query_options));
+    }
+
+    let mut tables = vec!();
+    for table in schema_import.tables.iter() {
+        let (table_name, table_options) = table_options_for(table, &options);
+        tables.extend(db::tables_to_import(&schema_import.schema, &table_name, &table_options));
+    }
+
+    tables
+}
+
+// The time window of the job replaces the one given by command line, whichever way each of them is given
+fn time_window_of(schema_import:&SchemaImport) -> (String, String) {
+    if schema_import.since.is_some() || schema_import.between.is_some() {
+        return (schema_import.since.to_owned().unwrap_or_default(), schema_import.between.to_owned().unwrap_or_default());
+    }
+
+    (config::CONFIG_PROPERTIES.since.to_owned(), config::CONFIG_PROPERTIES.between.to_owned())
+}
+
+fn table_in_schema(name:&str, schema:&str) -> (String, String) {
+    match name.split_once('.') {
+        Some((table_schema, table)) => (table_schema.to_string(), table.to_string()),
+        None => (schema.to_string(), name.to_string())
+    }
+}
+
+fn job_options_for(schema_import:&SchemaImport, masking_rules:&[MaskingRule]) -> ImportOptions {
+    let mut where_clause = schema_import.where_clause.as_ref().unwrap_or(String::from("").borrow()).to_owned();
     // Where clause is optional. If empty, it looks it's parsed as '~' for obscure reasons
-    if where_clause != "~" {
-        checked_where_clause = where_clause;
+    if where_clause == "~" {
+        where_clause = String::from("");
+    }
+
+    ImportOptions {
+        where_clause,
+        truncate: schema_import.truncate.unwrap_or(false),
+        cascade: schema_import.cascade.unwrap_or(false),
+        replace: schema_import.replace.unwrap_or(false),
+        create_missing: schema_import.create_missing.unwrap_or(config::CONFIG_PROPERTIES.create_missing),
+        add_missing_columns: schema_import.add_missing_columns.unwrap_or(config::CONFIG_PROPERTIES.add_missing_columns),
+        time_column: schema_import.time_column.to_owned().unwrap_or(config::CONFIG_PROPERTIES.time_column.to_owned()),
+        masking_rules: masking_rules.to_vec(),
+        ..Default::default()
+    }
+}
+
+// Get the name of the table and the options to import it, adding its own options to the ones of the job
+fn table_options_for(table:&TableImport, job_options:&ImportOptions) -> (String, ImportOptions) {
+    match table {
+        TableImport::Name(name) => (name.to_owned(), job_options.clone()),
+        TableImport::WithOptions(table) => {
+            let mut table_options = job_options.clone();
+            table_options.renamed_columns = table.rename.to_owned().unwrap_or_default();
+            table_options.excluded_columns = table.exclude.to_owned().unwrap_or_default();
+            table_options.column_expressions = to_sql_expressions(table.columns.as_ref().unwrap_or(&HashMap::new()));
+            table_options.partitions = table.partitions.to_owned().unwrap_or_default();
+            table_options.include_children = table.include_children.unwrap_or(false);
+            table_options.chunk_key = table.chunk_key.to_owned().unwrap_or_default();
+            table_options.sample_percent = table.sample_percent;
+            table_options.sample_method = table.sample_method.to_owned().unwrap_or_default();
+            table_options.seed = table.seed.unwrap_or(0);
+            table_options.order_by = table.order_by.to_owned().unwrap_or_default();
+            table_options.limit = table.limit;
+            if let Some(time_column) = &table.time_column {
+                table_options.time_column = time_column.to_owned();
+            }
+            (table.name.to_owned(), table_options)
+        }
     }
+}
 
-    for table in tables {
-        db::import_table_from(schema.to_owned(), table.to_owned(),
-                              checked_where_clause.to_owned(), truncate, cascade);
+// Column expressions are SQL, but YAML parses simple constants like 42 or true as numbers or booleans
+fn to_sql_expressions(columns:&HashMap<String, Value>) -> HashMap<String, String> {
+    let mut expressions = HashMap::new();
+
+    for (column, value) in columns {
+        let expression = match value {
+            Value::String(s) => s.to_owned(),
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Null => String::from("NULL"),
+            _ => {
+                println!("Error: Expression for column {} must be a SQL expression. e.g. some_column::jsonb", column);
+                std::process::exit(1);
+            }
+        };
+        expressions.insert(column.to_owned(), expression);
     }
+
+    expressions
 }
@@ -0,0 +1,184 @@
+use dialoguer::{theme::ColorfulTheme, Select};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config;
+use crate::config::{CONFIG_PROPERTIES, ImportOptions};
+use crate::db;
+use crate::versions;
+
+// Whether any checked table can't be imported as is, for the exit code of the check command
+static INCOMPATIBLE_TABLES_FOUND: AtomicBool = AtomicBool::new(false);
+
+// Differences between a source table (or query) and its target table
+struct TableCheck {
+    // Problems that would make the import fail, or write wrong values
+    problems:Vec<String>,
+    // Differences the import already handles, or that may only reject some rows
+    notes:Vec<String>,
+    // Whether the table can still be imported without its incompatible columns (i.e. both tables exist)
+    can_continue:bool,
+    // Source columns whose type doesn't match the target one
+    incompatible_columns:Vec<String>
+}
+
+pub fn incompatible_tables_found() -> bool {
+    INCOMPATIBLE_TABLES_FOUND.load(Ordering::Relaxed)
+}
+
+// Compare the columns and constraints of every table to import with the target ones, before anything is truncated or imported.
+// Tables with problems are imported, imported without their incompatible columns, skipped or abort the whole import,
+// as chosen with --on-incompatible or asked to the user
+pub fn check_tables(tables:Vec<(String, String, ImportOptions)>) -> Vec<(String, String, ImportOptions)> {
+    let on_incompatible = CONFIG_PROPERTIES.on_incompatible.to_uppercase();
+    if !["ASK", "ABORT", "CONTINUE", "SKIP"].contains(&on_incompatible.as_str()) {
+        println!("ERROR: Invalid value for --on-incompatible: {}. Valid values are ASK, ABORT, CONTINUE or SKIP", CONFIG_PROPERTIES.on_incompatible);
+        std::process::exit(1);
+    }
+
+    let mut checked_tables = vec!();
+    for (schema, table, mut options) in tables {
+        let table_check = check_table(&schema, &table, &options);
+        for note in &table_check.notes {
+            println!("INFO: {}", note);
+        }
+        for problem in &table_check.problems {
+            println!("WARNING: {}", problem);
+        }
+        if table_check.problems.is_empty() || config::is_check_command() {
+            if !table_check.problems.is_empty() {
+                INCOMPATIBLE_TABLES_FOUND.store(true, Ordering::Relaxed);
+            }
+            checked_tables.push((schema, table, options));
+            continue;
+        }
+
+        match choose_action(&schema, &table, &on_incompatible, table_check.can_continue).as_str() {
+            "CONTINUE" => {
+                if !table_check.incompatible_columns.is_empty() {
+                    println!("INFO: Importing {}.{} without columns {}", schema, table, table_check.incompatible_columns.join(", "));
+                }
+                options.excluded_columns.extend(table_check.incompatible_columns);
+                checked_tables.push((schema, table, options));
+            },
+            "SKIP" => println!("INFO: Skipping table {}.{}", schema, table),
+            _ => {
+                println!("ERROR: Table {}.{} can't be imported as is. Aborting the import", schema, table);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    checked_tables
+}
+
+// Batch files can't ask, so they abort unless told otherwise. Without both tables there's nothing to continue with
+fn choose_action(schema:&str, table:&str, on_incompatible:&str, can_continue:bool) -> String {
+    if on_incompatible == "CONTINUE" && !can_continue {
+        return String::from("ABORT");
+    }
+    if on_incompatible != "ASK" {
+        return on_incompatible.to_string();
+    }
+    if !CONFIG_PROPERTIES.batch_filename.is_empty() {
+        return String::from("ABORT");
+    }
+
+    let mut actions = vec!(("ABORT", String::from("Abort the import")));
+    if can_continue {
+        actions.push(("CONTINUE", String::from("Continue importing only the compatible columns")));
+    }
+    actions.push(("SKIP", format!("Skip table {}.{}", schema, table)));
+
+    let items:Vec<&String> = actions.iter().map(|(_, item)| item).collect();
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Table {}.{} doesn't match the target one. What do you want to do?", schema, table))
+        .default(0)
+        .items(&items[..])
+        .interact()
+        .unwrap();
+
+    actions[selection].0.to_string()
+}
+
+fn check_table(schema:&str, table:&str, options:&ImportOptions) -> TableCheck {
+    let mut table_check = TableCheck { problems: vec!(), notes: vec!(), can_continue: true, incompatible_columns: vec!() };
+
+    let source_columns = db::get_source_columns(schema, table, options);
+    if source_columns.is_empty() {
+        table_check.problems.push(format!("Table {}.{} doesn't exist in source DB", schema, table));
+        table_check.can_continue = false;
+        return table_check;
+    }
+
+    let target_columns = db::get_columns_of_table(config::get_target_db_url().as_str(), *versions::TARGET_VERSION, schema, table);
+    if target_columns.is_empty() {
+        table_check.can_continue = false;
+        // Missing tables are only left in the target DB by a dry run or a check
+        if options.create_missing {
+            table_check.notes.push(format!("Table {}.{} doesn't exist in target DB. It will be created", schema, table));
+        }
+        else {
+            table_check.problems.push(format!("Table {}.{} doesn't exist in target DB", schema, table));
+        }
+        return table_check;
+    }
+
+    let generate_identity_values = CONFIG_PROPERTIES.identity_values == "TARGET";
+    let mut imported_target_columns:Vec<String> = options.column_expressions.keys().cloned().collect();
+
+    for source_column in &source_columns {
+        if options.excluded_columns.contains(&source_column.name) {
+            continue;
+        }
+        let target_name = options.renamed_columns.get(&source_column.name).unwrap_or(&source_column.name);
+        if options.column_expressions.contains_key(target_name) {
+            continue;
+        }
+
+        let target_column = match target_columns.iter().find(|column| &column.name == target_name) {
+            Some(column) => column,
+            None => {
+                table_check.notes.push(format!("Column {} doesn't exist in target table {}.{}. It won't be imported", target_name, schema, table));
+                continue;
+            }
+        };
+        // Never written, so their types don't matter
+        if target_column.generated || (target_column.identity && generate_identity_values) {
+            continue;
+        }
+
+        if source_column.data_type != target_column.data_type {
+            table_check.problems.push(format!("Column {} is {} in source but {} in target table {}.{}", target_name, source_column.data_type,
+                target_column.data_type, schema, table));
+            table_check.incompatible_columns.push(source_column.name.to_owned());
+            continue;
+        }
+        // Nullability of query columns is unknown
+        if options.query.is_empty() && target_column.not_null && !source_column.not_null {
+            table_check.notes.push(format!("Column {} is NOT NULL in target table {}.{} but not in source. Rows with NULL values will be rejected",
+                target_name, schema, table));
+        }
+        imported_target_columns.push(target_name.to_owned());
+    }
+
+    for target_column in &target_columns {
+        if target_column.not_null && !target_column.has_default && !target_column.identity && !target_column.generated
+            && !imported_target_columns.contains(&target_column.name) {
+            table_check.problems.push(format!("Column {} is NOT NULL in target table {}.{} without a default, but no source column is imported into it",
+                target_column.name, schema, table));
+        }
+    }
+
+    // Query results don't have constraints to compare with
+    if options.query.is_empty() {
+        let source_constraints = db::get_constraints_of_table(config::get_source_db_url().as_str(), schema, table);
+        for (name, definition) in db::get_constraints_of_table(config::get_target_db_url().as_str(), schema, table) {
+            if !source_constraints.iter().any(|(_, source_definition)| source_definition == &definition) {
+                table_check.notes.push(format!("Constraint {} of target table {}.{} ({}) doesn't exist in source. Rows violating it will be rejected",
+                    name, schema, table, definition));
+            }
+        }
+    }
+
+    table_check
+}
@@ -2,29 +2,118 @@ use core::str::FromStr;
 use clap::Parser;
 use lazy_static::lazy_static;
 use regex::{Regex, Error};
+use std::collections::HashMap;
 use std::env;
+use std::sync::RwLock;
+
+use crate::masking::{MaskedColumn, MaskingRule};
 
 // DEFAULT DB CONFIG
 pub const SOURCE_DB_CONNECTION:&str = "postgres:postgres@localhost:5432/postgres";
 pub const TARGET_DB_CONNECTION:&str = "postgres:postgres@localhost:5555/postgres";
 
+// Settings that change how values are written in COPY text format, pinned to the same values on all source and target sessions.
+// client_encoding is always UTF8, as the postgres driver forces it
+pub const SESSION_SETTINGS:[(&str, &str); 4] = [("DateStyle", "ISO,YMD"), ("IntervalStyle", "postgres"), ("TimeZone", "UTC"),
+    ("extra_float_digits", "3")];
+
 // OTHER DEFAULT CONFIG VALUES
 pub const DEFAULT_MAX_THREADS:i64 = 8;
 pub const DEFAULT_ROWS_FOR_INSERT:i64 = 10000;
 pub const DEFAULT_ROWS_FOR_SELECT:i64 = 50000;
 pub const ERROR_LOG_ENABLED_BY_DEFAULT:bool = false;
 pub const DEFAULT_IMPORTER_IMPL:&str = "COPY";
+pub const DEFAULT_MAX_REJECTS:i64 = 1000;
+pub const DEFAULT_IDENTITY_VALUES:&str = "SOURCE";
+pub const LARGE_OBJECTS_ENABLED_BY_DEFAULT:bool = false;
+pub const CREATE_MISSING_ENABLED_BY_DEFAULT:bool = false;
+pub const ADD_MISSING_COLUMNS_ENABLED_BY_DEFAULT:bool = false;
+pub const DRY_RUN_ENABLED_BY_DEFAULT:bool = false;
+pub const DEFAULT_COMMAND:&str = "import";
+pub const DEFAULT_ON_INCOMPATIBLE:&str = "ASK";
+pub const DEFAULT_TABLES_WITHOUT_FILTER:&str = "IMPORT";
+pub const CHECK_ENCODING_ENABLED_BY_DEFAULT:bool = true;
 
 // Creates a global shared static singleton with all config values
 lazy_static! {
     pub static ref CONFIG_PROPERTIES: ConfigProperties = populate_properties();
 }
 
+// Databases chosen interactively or by a batch job, used instead of the ones in the connection URLs
+lazy_static! {
+    static ref SOURCE_DATABASE: RwLock<Option<String>> = RwLock::new(None);
+    static ref TARGET_DATABASE: RwLock<Option<String>> = RwLock::new(None);
+}
+
+// Options chosen by the user, interactively or in a batch job, to import a table
+#[derive(Clone, Default)]
+pub struct ImportOptions {
+    pub where_clause:String,
+    pub truncate:bool,
+    pub cascade:bool,
+    pub replace:bool,
+    // Source column name => Target column name
+    pub renamed_columns:HashMap<String, String>,
+    pub excluded_columns:Vec<String>,
+    // Target column name => SQL expression evaluated in source DB to get its value
+    pub column_expressions:HashMap<String, String>,
+    // Partitions to import from a partitioned table. All of them if empty
+    pub partitions:Vec<String>,
+    // Import also the tables inheriting from the table, each one to its own target table
+    pub include_children:bool,
+    // Columns with unique values to split the rows in chunks for a multi-thread import, instead of a UNIQUE constraint of the table
+    pub chunk_key:Vec<String>,
+    // SQL query run in the source DB to get the rows to import, instead of reading them from the table with the same name
+    pub query:String,
+    // Create the target table from the source one if it doesn't exist
+    pub create_missing:bool,
+    // Add to the target table the source columns it doesn't have
+    pub add_missing_columns:bool,
+    // Import only a random sample of this percentage of the rows (TABLESAMPLE), always the same one for the same seed
+    pub sample_percent:Option<f64>,
+    // BERNOULLI (random rows) or SYSTEM (random pages, faster). BERNOULLI if empty
+    pub sample_method:String,
+    pub seed:i64,
+    // Import only the first rows in this order (e.g. created_at DESC), at most limit of them
+    pub order_by:String,
+    pub limit:Option<i64>,
+    // Timestamp or date column the time window (--since or --between) is applied to. Also used to split the rows in chunks
+    // for a multi-thread import of tables without a UNIQUE constraint
+    pub time_column:String,
+    // Rules to mask the values of some columns in the stream of rows between source and target
+    pub masking_rules:Vec<MaskingRule>
+}
+
 // Encapsulates all DB and config info needed for a worker thread to do an import
+#[derive(Clone)]
 pub struct ImportConfig {
     pub schema:String,
     pub table:String,
+    // Relation the rows are read from in the source DB (the table itself or one of its partitions), already quoted
+    pub source_table:String,
+    // Relation the rows are deleted from in the target DB in REPLACE mode, already quoted
+    pub target_table:String,
+    // Rows deleted from the target table in REPLACE mode
     pub where_clause:String,
+    // Rows read from the source relation. Empty when the source relation is a sample, as it's already applied inside it
+    pub source_where_clause:String,
+    // ORDER BY of the rows to load, so the rows referencing other rows of the same table are loaded after them. Empty for any order
+    pub rows_order:String,
+    pub replace:bool,
+    // The target client is inside a transaction while loading the rows (REPLACE mode, or the tables of a foreign key cycle),
+    // so a failed statement must not abort it
+    pub in_transaction:bool,
+    // Columns to import, matched by name. Both lists have the same length and order.
+    // Source columns can also be SQL expressions computing the value for the target column
+    pub source_columns:Vec<String>,
+    pub target_columns:Vec<String>,
+    pub overriding_system_value:bool,
+    pub large_object_columns:Vec<usize>,
+    // Columns masked before writing them to target, with the rule masking each of them
+    pub masked_columns:Vec<MaskedColumn>,
+    // Position in the column lists of the columns sent as text by the QUERY importer, as their binary format may not be
+    // the one of the target column, with the target type to cast them to
+    pub text_columns:Vec<(usize, String)>,
     pub source_db_url:String,
     pub target_db_url:String,    
     pub importer_impl:String
@@ -67,7 +156,28 @@ pub enum ConfigProperty {
     RowsToExecuteSelect(i64),
     ErrorLogEnabled(bool),
     ImporterImplementation(String),
-    BatchFileName(String)
+    BatchFileName(String),
+    RejectFileName(String),
+    MaxRejects(i64),
+    IdentityValues(String),
+    LargeObjectsEnabled(bool),
+    Query(String),
+    TargetTable(String),
+    CreateMissingEnabled(bool),
+    AddMissingColumnsEnabled(bool),
+    DryRunEnabled(bool),
+    Command(String),
+    OnIncompatible(String),
+    FilterColumn(String),
+    TablesWithoutFilter(String),
+    KeysFile(String),
+    KeyColumn(String),
+    TimeColumn(String),
+    Since(String),
+    Between(String),
+    MaskingFile(String),
+    MaskingKey(String),
+    CheckEncodingEnabled(bool)
 }
 
 pub struct ConfigProperties {
@@ -78,7 +188,28 @@ pub struct ConfigProperties {
     pub rows_select: i64,
     pub error_log: bool,
     pub importer_impl: String,
-    pub batch_filename: String
+    pub batch_filename: String,
+    pub reject_filename: String,
+    pub max_rejects: i64,
+    pub identity_values: String,
+    pub large_objects: bool,
+    pub query: String,
+    pub target_table: String,
+    pub create_missing: bool,
+    pub add_missing_columns: bool,
+    pub dry_run: bool,
+    pub command: String,
+    pub on_incompatible: String,
+    pub filter_column: String,
+    pub tables_without_filter: String,
+    pub keys_file: String,
+    pub key_column: String,
+    pub time_column: String,
+    pub since: String,
+    pub between: String,
+    pub masking_file: String,
+    pub masking_key: String,
+    pub check_encoding: bool
 }
 
 const ABOUT_MSG:&str = "Command line tool to export data from a Postgres DB and insert it to another one";
@@ -93,6 +224,8 @@ The name of the env vars is the same as the options, but using Upper case and un
 #[derive(Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"), author = "Miguel Rivero", about = ABOUT_MSG, after_help = AFTER_HELP_MSG)]
 struct Opts {
+    /// Command to run: import (default) or check, to only check that the selected tables can be imported
+    command: Option<String>,
     /// Source DB URL:  user:secret@host:port/dbname
     #[clap(long, short)]
     source: Option<String>,
@@ -111,35 +244,128 @@ struct Opts {
     /// Enable parse errors logging
     #[clap(long)]
     error_log: Option<bool>,
-    /// Use SELECT or COPY implementation
+    /// Use QUERY (prepared INSERTs) or COPY implementation
     #[clap(long)]
     importer_impl: Option<String>,
     /// Batch file to process
     #[clap(long)]
-    batch_filename: Option<String>
+    batch_filename: Option<String>,
+    /// Keep importing when a row is rejected by the target DB, writing rejected rows to this file
+    #[clap(long)]
+    reject_filename: Option<String>,
+    /// Max number of rejected rows before aborting the import (only used along with --reject-filename)
+    #[clap(long)]
+    max_rejects: Option<i64>,
+    /// Keep SOURCE values for identity columns or let TARGET DB generate new ones
+    #[clap(long)]
+    identity_values: Option<String>,
+    /// Copy the large objects referenced by oid columns, instead of just the oid values
+    #[clap(long)]
+    large_objects: Option<bool>,
+    /// Import the result of this SQL query, run in the source DB, instead of choosing tables
+    #[clap(long)]
+    query: Option<String>,
+    /// Target table for the result of --query:  schema.table
+    #[clap(long)]
+    target_table: Option<String>,
+    /// Create the target tables that don't exist, with the columns, keys and indexes of the source ones
+    #[clap(long)]
+    create_missing: Option<bool>,
+    /// Add to the target tables the source columns they don't have
+    #[clap(long)]
+    add_missing_columns: Option<bool>,
+    /// List the objects and tables that would be created in the target DB, without creating or importing anything
+    #[clap(long)]
+    dry_run: Option<bool>,
+    /// What to do with tables whose columns don't match the target ones: ASK, ABORT, CONTINUE (with the compatible columns) or SKIP
+    #[clap(long)]
+    on_incompatible: Option<String>,
+    /// Import only the rows with this value in every table that has the column:  column=value
+    #[clap(long)]
+    filter_column: Option<String>,
+    /// What to do with tables that don't have the --filter-column: IMPORT all their rows or SKIP them
+    #[clap(long)]
+    tables_without_filter: Option<String>,
+    /// File with the keys of the rows to import, one per line (e.g. a CSV whose first column is the key)
+    #[clap(long)]
+    keys_file: Option<String>,
+    /// Column of the tables to find the keys of --keys-file in
+    #[clap(long)]
+    key_column: Option<String>,
+    /// Timestamp or date column of the tables to import a time window of their rows from
+    #[clap(long)]
+    time_column: Option<String>,
+    /// Import only the rows of the last period (e.g. 7d, 12h, 30m, 2w) or since a date, in the --time-column of each table
+    #[clap(long)]
+    since: Option<String>,
+    /// Import only the rows between two dates (e.g. 2026-09-01..2026-10-01, the last one excluded), in the --time-column of each table
+    #[clap(long)]
+    between: Option<String>,
+    /// YAML file with the rules to mask the values of some columns before they're written to the target DB
+    #[clap(long)]
+    masking_file: Option<String>,
+    /// Secret key for the deterministic masking rules, so masked values can't be guessed by masking known values (better as env var)
+    #[clap(long)]
+    masking_key: Option<String>,
+    /// Refuse to import when the source DB encoding can't be converted to the target one without losing characters
+    #[clap(long)]
+    check_encoding: Option<bool>
 }
 
 pub fn get_source_db_url() -> String {
-    return get_source_db_url_with_hiding(false);
+    with_session_settings(get_source_db_url_with_hiding(false))
 }
 
 pub fn get_target_db_url() -> String {
-    return get_target_db_url_with_hiding(false);
+    with_session_settings(get_target_db_url_with_hiding(false))
+}
+
+// URL without pinned session settings, to check the ones used by default in the server
+pub fn get_source_db_url_with_server_settings() -> String {
+    get_source_db_url_with_hiding(false)
+}
+
+pub fn get_target_db_url_with_server_settings() -> String {
+    get_target_db_url_with_hiding(false)
 }
 
 pub fn get_source_db_url_with_hiding(hide_pass:bool) -> String {
-    to_postgres_driver_params(&CONFIG_PROPERTIES.source , hide_pass)
+    to_postgres_driver_params(&CONFIG_PROPERTIES.source, &get_source_database(), hide_pass)
 }
 
 pub fn get_target_db_url_with_hiding(hide_pass:bool) -> String {
-    to_postgres_driver_params(&CONFIG_PROPERTIES.target, hide_pass)
+    to_postgres_driver_params(&CONFIG_PROPERTIES.target, &get_target_database(), hide_pass)
 }
 
-fn to_postgres_driver_params(connection_params:&ConnectionParams, hide_pass:bool) -> String {
+// Use other databases of the same source and target servers. None to use the ones in the connection URLs
+pub fn use_databases(source_database:Option<String>, target_database:Option<String>) {
+    *SOURCE_DATABASE.write().unwrap() = source_database;
+    *TARGET_DATABASE.write().unwrap() = target_database;
+}
+
+pub fn get_source_database() -> String {
+    SOURCE_DATABASE.read().unwrap().to_owned().unwrap_or(CONFIG_PROPERTIES.source.dbname.to_owned())
+}
+
+pub fn get_target_database() -> String {
+    TARGET_DATABASE.read().unwrap().to_owned().unwrap_or(CONFIG_PROPERTIES.target.dbname.to_owned())
+}
+
+// The check command only reports what an import would do
+pub fn is_check_command() -> bool {
+    CONFIG_PROPERTIES.command == "check"
+}
+
+// Nothing is created in the target DB in a dry run or a check
+pub fn is_dry_run() -> bool {
+    CONFIG_PROPERTIES.dry_run || is_check_command()
+}
+
+fn to_postgres_driver_params(connection_params:&ConnectionParams, database:&str, hide_pass:bool) -> String {
 
     let host:String = connection_params.host.to_owned();
     let port:String = connection_params.port.to_owned();
-    let database:String = connection_params.dbname.to_owned();
+    let database:String = database.to_owned();
     let user:String = connection_params.user.to_owned();
     let mut pass:String = connection_params.pass.to_owned();
     if hide_pass {
@@ -148,6 +374,13 @@ fn to_postgres_driver_params(connection_params:&ConnectionParams, hide_pass:bool
     format!("host='{}' port='{}' dbname='{}' user='{}' password='{}'", host , port, database, user, pass)
 }
 
+fn with_session_settings(db_url:String) -> String {
+    let options:Vec<String> = SESSION_SETTINGS.iter()
+        .map(|(name, value)| format!("-c {}={}", name, value))
+        .collect();
+    format!("{} options='{}'", db_url, options.join(" "))
+}
+
 fn environment_or_default<T> (env_key:&str, default_value: T) -> T where T: FromStr {
     match env::var(env_key) {
         Ok(env_value) => { 
@@ -197,9 +430,96 @@ fn populate_properties() -> ConfigProperties {
         ConfigProperty::BatchFileName(b) => b,
         _ => panic!("Wrong enum type") 
     };
+    let reject_filename = match get_most_prioritary_value("REJECT_FILENAME") {
+        ConfigProperty::RejectFileName(r) => r,
+        _ => panic!("Wrong enum type") 
+    };
+    let max_rejects = match get_most_prioritary_value("MAX_REJECTS") {
+        ConfigProperty::MaxRejects(m) => m,
+        _ => panic!("Wrong enum type") 
+    };
+    let identity_values = match get_most_prioritary_value("IDENTITY_VALUES") {
+        ConfigProperty::IdentityValues(i) => i,
+        _ => panic!("Wrong enum type") 
+    };
+    let large_objects = match get_most_prioritary_value("LARGE_OBJECTS") {
+        ConfigProperty::LargeObjectsEnabled(l) => l,
+        _ => panic!("Wrong enum type") 
+    };
+    let query = match get_most_prioritary_value("QUERY") {
+        ConfigProperty::Query(q) => q,
+        _ => panic!("Wrong enum type") 
+    };
+    let target_table = match get_most_prioritary_value("TARGET_TABLE") {
+        ConfigProperty::TargetTable(t) => t,
+        _ => panic!("Wrong enum type") 
+    };
+    let create_missing = match get_most_prioritary_value("CREATE_MISSING") {
+        ConfigProperty::CreateMissingEnabled(c) => c,
+        _ => panic!("Wrong enum type") 
+    };
+    let add_missing_columns = match get_most_prioritary_value("ADD_MISSING_COLUMNS") {
+        ConfigProperty::AddMissingColumnsEnabled(a) => a,
+        _ => panic!("Wrong enum type") 
+    };
+    let dry_run = match get_most_prioritary_value("DRY_RUN") {
+        ConfigProperty::DryRunEnabled(d) => d,
+        _ => panic!("Wrong enum type") 
+    };
+    let command = match get_most_prioritary_value("COMMAND") {
+        ConfigProperty::Command(c) => c,
+        _ => panic!("Wrong enum type") 
+    };
+    let on_incompatible = match get_most_prioritary_value("ON_INCOMPATIBLE") {
+        ConfigProperty::OnIncompatible(o) => o,
+        _ => panic!("Wrong enum type") 
+    };
+    let filter_column = match get_most_prioritary_value("FILTER_COLUMN") {
+        ConfigProperty::FilterColumn(f) => f,
+        _ => panic!("Wrong enum type") 
+    };
+    let tables_without_filter = match get_most_prioritary_value("TABLES_WITHOUT_FILTER") {
+        ConfigProperty::TablesWithoutFilter(t) => t,
+        _ => panic!("Wrong enum type") 
+    };
+    let keys_file = match get_most_prioritary_value("KEYS_FILE") {
+        ConfigProperty::KeysFile(k) => k,
+        _ => panic!("Wrong enum type") 
+    };
+    let key_column = match get_most_prioritary_value("KEY_COLUMN") {
+        ConfigProperty::KeyColumn(k) => k,
+        _ => panic!("Wrong enum type") 
+    };
+    let time_column = match get_most_prioritary_value("TIME_COLUMN") {
+        ConfigProperty::TimeColumn(t) => t,
+        _ => panic!("Wrong enum type") 
+    };
+    let since = match get_most_prioritary_value("SINCE") {
+        ConfigProperty::Since(s) => s,
+        _ => panic!("Wrong enum type") 
+    };
+    let between = match get_most_prioritary_value("BETWEEN") {
+        ConfigProperty::Between(b) => b,
+        _ => panic!("Wrong enum type") 
+    };
+    let masking_file = match get_most_prioritary_value("MASKING_FILE") {
+        ConfigProperty::MaskingFile(m) => m,
+        _ => panic!("Wrong enum type") 
+    };
+    let masking_key = match get_most_prioritary_value("MASKING_KEY") {
+        ConfigProperty::MaskingKey(m) => m,
+        _ => panic!("Wrong enum type") 
+    };
+    let check_encoding = match get_most_prioritary_value("CHECK_ENCODING") {
+        ConfigProperty::CheckEncodingEnabled(c) => c,
+        _ => panic!("Wrong enum type") 
+    };
 
     return ConfigProperties { source: source_connection, target: target_connection, max_threads: max_threads, rows_insert: rows_insert,
-        rows_select:rows_select, error_log: error_log, importer_impl: importer_impl, batch_filename: batch_filename };
+        rows_select:rows_select, error_log: error_log, importer_impl: importer_impl, batch_filename: batch_filename,
+        reject_filename, max_rejects, identity_values, large_objects, query, target_table,
+        create_missing, add_missing_columns, dry_run, command, on_incompatible, filter_column, tables_without_filter,
+        keys_file, key_column, time_column, since, between, masking_file, masking_key, check_encoding };
 }
 
 // Get the config param, looking for the value in the following order:
@@ -221,6 +541,31 @@ fn get_most_prioritary_value(env_key:&str) -> ConfigProperty {
         "ERROR_LOG" =>  ConfigProperty::ErrorLogEnabled(get_value_from(opts.error_log, "ERROR_LOG", ERROR_LOG_ENABLED_BY_DEFAULT)),
         "IMPORTER_IMPL" =>  ConfigProperty::ImporterImplementation(get_value_from(opts.importer_impl, "IMPORTER_IMPL", DEFAULT_IMPORTER_IMPL.to_owned())),
         "BATCH_FILENAME" =>  ConfigProperty::BatchFileName(get_value_from(opts.batch_filename, "BATCH_FILENAME", "".to_owned())),
+        "REJECT_FILENAME" =>  ConfigProperty::RejectFileName(get_value_from(opts.reject_filename, "REJECT_FILENAME", "".to_owned())),
+        "MAX_REJECTS" =>  ConfigProperty::MaxRejects(get_value_from(opts.max_rejects, "MAX_REJECTS", DEFAULT_MAX_REJECTS)),
+        "LARGE_OBJECTS" =>  ConfigProperty::LargeObjectsEnabled(get_value_from(opts.large_objects, "LARGE_OBJECTS", LARGE_OBJECTS_ENABLED_BY_DEFAULT)),
+        "IDENTITY_VALUES" =>  ConfigProperty::IdentityValues(get_value_from(opts.identity_values, "IDENTITY_VALUES", DEFAULT_IDENTITY_VALUES.to_owned())),
+        "QUERY" =>  ConfigProperty::Query(get_value_from(opts.query, "QUERY", "".to_owned())),
+        "TARGET_TABLE" =>  ConfigProperty::TargetTable(get_value_from(opts.target_table, "TARGET_TABLE", "".to_owned())),
+        "CREATE_MISSING" =>  ConfigProperty::CreateMissingEnabled(get_value_from(opts.create_missing, "CREATE_MISSING", CREATE_MISSING_ENABLED_BY_DEFAULT)),
+        "ADD_MISSING_COLUMNS" =>  ConfigProperty::AddMissingColumnsEnabled(get_value_from(opts.add_missing_columns, "ADD_MISSING_COLUMNS",
+            ADD_MISSING_COLUMNS_ENABLED_BY_DEFAULT)),
+        "DRY_RUN" =>  ConfigProperty::DryRunEnabled(get_value_from(opts.dry_run, "DRY_RUN", DRY_RUN_ENABLED_BY_DEFAULT)),
+        // Only from command line, as COMMAND is a too common name for an env var
+        "COMMAND" =>  ConfigProperty::Command(opts.command.unwrap_or(DEFAULT_COMMAND.to_owned())),
+        "ON_INCOMPATIBLE" =>  ConfigProperty::OnIncompatible(get_value_from(opts.on_incompatible, "ON_INCOMPATIBLE", DEFAULT_ON_INCOMPATIBLE.to_owned())),
+        "FILTER_COLUMN" =>  ConfigProperty::FilterColumn(get_value_from(opts.filter_column, "FILTER_COLUMN", "".to_owned())),
+        "TABLES_WITHOUT_FILTER" =>  ConfigProperty::TablesWithoutFilter(get_value_from(opts.tables_without_filter, "TABLES_WITHOUT_FILTER",
+            DEFAULT_TABLES_WITHOUT_FILTER.to_owned())),
+        "KEYS_FILE" =>  ConfigProperty::KeysFile(get_value_from(opts.keys_file, "KEYS_FILE", "".to_owned())),
+        "KEY_COLUMN" =>  ConfigProperty::KeyColumn(get_value_from(opts.key_column, "KEY_COLUMN", "".to_owned())),
+        "TIME_COLUMN" =>  ConfigProperty::TimeColumn(get_value_from(opts.time_column, "TIME_COLUMN", "".to_owned())),
+        "SINCE" =>  ConfigProperty::Since(get_value_from(opts.since, "SINCE", "".to_owned())),
+        "BETWEEN" =>  ConfigProperty::Between(get_value_from(opts.between, "BETWEEN", "".to_owned())),
+        "MASKING_FILE" =>  ConfigProperty::MaskingFile(get_value_from(opts.masking_file, "MASKING_FILE", "".to_owned())),
+        "MASKING_KEY" =>  ConfigProperty::MaskingKey(get_value_from(opts.masking_key, "MASKING_KEY", "".to_owned())),
+        "CHECK_ENCODING" =>  ConfigProperty::CheckEncodingEnabled(get_value_from(opts.check_encoding, "CHECK_ENCODING",
+            CHECK_ENCODING_ENABLED_BY_DEFAULT)),
         _ => panic!("Config parameter key requested not recognized: {}", env_key)
     }
 
@@ -1,26 +1,73 @@
+use std::borrow::Cow;
 use std::io::{Read, Write};
-use crate::config::ImportConfig;
+use postgres::{Client, Error};
+use crate::config::{ImportConfig, CONFIG_PROPERTIES};
 use crate::db::{TableImporter, DBClients, TableChunk};
+use crate::large_objects;
+use crate::masking;
+use crate::rejects;
+use crate::sql;
 
 pub struct CopyImporter;
 
 impl TableImporter for CopyImporter {
 
-    fn import_table_chunk(&self, import_config:&ImportConfig, db_clients:&mut DBClients, chunk:&TableChunk) {
+    fn import_table_chunk(&self, import_config:&ImportConfig, db_clients:&mut DBClients, chunk:&TableChunk) -> Result<(), Box<dyn std::error::Error>> {
         // Create copy query to extract data
-        let select_query = format!("SELECT * FROM {}.{} {} ORDER BY {} OFFSET {} LIMIT {}",
-            import_config.schema, import_config.table, chunk.where_clause, chunk.order_by, chunk.offset, chunk.limit);
+        let select_query = format!("SELECT {} FROM {} {} ORDER BY {} OFFSET {} LIMIT {}",
+            import_config.source_columns.join(", "), import_config.source_table, chunk.where_clause,
+            chunk.order_by, chunk.offset, chunk.limit);
         let copy_out_query:String = format!("COPY ({}) TO STDOUT", select_query);
-    
-        let mut reader = db_clients.source_client.copy_out(copy_out_query.as_str()).unwrap();
+
+        let mut reader = db_clients.source_client.copy_out(copy_out_query.as_str())?;
         let mut buf = vec![];
-        reader.read_to_end(&mut buf).unwrap();
-        
-        // Create copy query to import data
-        let copy_in_query:String = format!("COPY {}.{} FROM STDIN", import_config.schema, import_config.table);
-        let mut writer = db_clients.target_client.copy_in(copy_in_query.as_str()).unwrap();
-        writer.write_all(&buf).unwrap();
-        writer.finish().unwrap();
+        reader.read_to_end(&mut buf)?;
+
+        write_to_target(import_config, &mut db_clients.target_client, &mut db_clients.large_objects_client, &buf)?;
+        Ok(())
+    }
+
+}
+
+// Write a buffer of rows in COPY text format to the target table, copying the large objects they reference with the large objects client.
+// If a reject file is configured, a failing buffer doesn't abort the import: its rows are retried to isolate the rejected ones
+pub fn write_to_target(import_config:&ImportConfig, target_client:&mut Client, large_objects_client:&mut Option<Client>, buffer:&[u8])
+    -> Result<(), Error> {
+    let mut buffer = Cow::Borrowed(buffer);
+    // Masked values are never copied, not even the large objects they could reference
+    if !import_config.masked_columns.is_empty() {
+        buffer = Cow::Owned(masking::mask_buffer(import_config, &buffer));
+    }
+    // Large objects must be copied before the rows, so the rows can reference them by their new oids in the target DB
+    if let Some(large_objects_client) = large_objects_client {
+        buffer = Cow::Owned(large_objects::copy_large_objects_in_buffer(import_config, large_objects_client, target_client, &buffer)?);
     }
 
-}
\ No newline at end of file
+    if CONFIG_PROPERTIES.reject_filename.is_empty() {
+        copy_in(import_config, target_client, &buffer)?;
+    }
+    else {
+        rejects::write_isolating_rejects(import_config, target_client, &buffer);
+    }
+
+    Ok(())
+}
+
+pub fn copy_in(import_config:&ImportConfig, target_client:&mut Client, buffer:&[u8]) -> Result<u64, Error> {
+    // Create copy query to import data
+    let copy_in_query:String = format!("COPY {} ({}) FROM STDIN", sql::qualified_table(&import_config.schema, &import_config.table),
+        sql::quote_idents(&import_config.target_columns));
+    let mut writer = target_client.copy_in(copy_in_query.as_str())?;
+    // Errors of the server are reported when finishing the COPY
+    if let Err(write_error) = writer.write_all(buffer) {
+        return match writer.finish() {
+            Err(error) => Err(error),
+            // The rows may not have reached the target table, so they can't be counted as imported
+            Ok(_) => {
+                println!("ERROR: Couldn't write rows to target table {}.{} | Error: {}", import_config.schema, import_config.table, write_error);
+                std::process::exit(1);
+            }
+        };
+    }
+    writer.finish()
+}
@@ -1,16 +1,30 @@
 use postgres::{Client, NoTls};
+use postgres::types::Type;
+use std::collections::HashMap;
 use std::time::{Instant};
 use std::sync::Arc;
 
 use crate::config;
-use crate::config::{CONFIG_PROPERTIES, ImportConfig};
+use crate::config::{CONFIG_PROPERTIES, ImportConfig, ImportOptions};
+use crate::filter;
+use crate::masking;
+use crate::masking::MaskedColumn;
 
 use crate::single_import;
 use crate::multi_import;
+use crate::partition_import;
+use crate::rejects;
+use crate::sql;
+use crate::versions;
+
+// Objects created by users (types included) get oids from this one on, so they're different in each server
+const FIRST_USER_OID:u32 = 16384;
 
 pub struct DBClients {
     pub source_client:Client,
-    pub target_client:Client
+    pub target_client:Client,
+    // Source connection reading the large objects referenced by the rows, in the same snapshot. None if they don't reference any
+    pub large_objects_client:Option<Client>
 }
 
 pub struct TableChunk {
@@ -20,8 +34,60 @@ pub struct TableChunk {
     pub order_by:String
 }
 
+pub struct TableColumn {
+    pub name:String,
+    // GENERATED ALWAYS AS (...) STORED columns can't be written
+    pub generated:bool,
+    // GENERATED ALWAYS/BY DEFAULT AS IDENTITY columns
+    pub identity:bool,
+    // oid (or lo) columns that may reference large objects
+    pub large_object:bool,
+    // Type without modifiers (e.g. character varying), to compare the source and target columns
+    pub data_type:String,
+    pub not_null:bool,
+    pub has_default:bool,
+    // Built-in type (or array of a built-in type), with the same oid and binary format in every server
+    pub built_in_type:bool,
+    // Max number of characters of varchar(n) and char(n) columns
+    pub max_length:Option<i32>
+}
+
+// Columns to read from source and write to target, in the same order
+pub struct ColumnMapping {
+    pub source_columns:Vec<String>,
+    pub target_columns:Vec<String>,
+    // Explicit values for identity columns are only accepted on INSERT with OVERRIDING SYSTEM VALUE
+    pub overriding_system_value:bool,
+    // Position in the column lists of the columns whose large objects must be copied too
+    pub large_object_columns:Vec<usize>,
+    // Columns masked before writing them to target, with the rule masking each of them
+    pub masked_columns:Vec<MaskedColumn>,
+    // Position in the column lists of the columns sent as text by the QUERY importer, with the target type to cast them to
+    pub text_columns:Vec<(usize, String)>
+}
+
+// Estimated size of a table. For partitioned tables it's the sum of all their partitions
+pub struct TableStats {
+    pub rows:u64,
+    pub size:String,
+    pub partitions:i64,
+    // Legacy inheritance: table this one inherits from, and number of tables inheriting directly from this one
+    pub parent:Option<String>,
+    pub children:i64,
+    // table, partitioned table, view, materialized view or foreign table
+    pub kind:String
+}
+
+// Leaf partition of a partitioned table, with the bounds of the values it keeps
+pub struct TablePartition {
+    pub schema:String,
+    pub name:String,
+    pub bound:String,
+    pub rows:u64
+}
+
 pub trait TableImporter {
-    fn import_table_chunk(&self, import_config:&ImportConfig, db_clients:&mut DBClients, chunk:&TableChunk);
+    fn import_table_chunk(&self, import_config:&ImportConfig, db_clients:&mut DBClients, chunk:&TableChunk) -> Result<(), Box<dyn std::error::Error>>;
 }
 
 pub fn get_available_schemas() -> Vec<String> {
@@ -42,21 +108,244 @@ pub fn get_available_schemas() -> Vec<String> {
     return schemas;
 }
 
-pub fn get_number_of_rows_for(schema:&str, table:&str) -> u64 {
+// Databases of the server the user can connect to
+pub fn get_available_databases(db_url:&str) -> Vec<String> {
+    let mut client = match Client::connect(db_url, NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let mut databases:Vec<String> = vec!();
+
+    for row in client.query("SELECT datname::text FROM pg_database WHERE datallowconn AND NOT datistemplate
+            AND has_database_privilege(datname, 'CONNECT') ORDER BY datname", &[]).unwrap() {
+        databases.push(row.get(0));
+    }
+
+    databases
+}
+
+// Server settings that affect how values are written and read in text format
+pub fn get_server_settings(db_url:&str) -> HashMap<String, String> {
+    let mut client = match Client::connect(db_url, NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let mut settings = HashMap::new();
+
+    for row in client.query("SELECT name, setting FROM pg_settings WHERE name IN
+            ('server_encoding', 'DateStyle', 'IntervalStyle', 'TimeZone', 'extra_float_digits')", &[]).unwrap() {
+        settings.insert(row.get(0), row.get(1));
+    }
+
+    settings
+}
+
+pub fn get_server_version_num(db_url:&str) -> i32 {
+    let mut client = match Client::connect(db_url, NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    client.query_one("SELECT current_setting('server_version_num')::int", &[]).unwrap().get(0)
+}
+
+pub fn get_table_stats_for(schema:&str, table:&str) -> TableStats {
     let mut count_db_client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
         Ok(client) => client,
         Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
     };
 
-    // Estimate the rows
-    let estimate_query = format!("SELECT reltuples::bigint AS estimate FROM pg_class WHERE  oid = '{}.{}'::regclass;", schema, table);
+    // Estimate the rows and size. Partitioned tables ('p') don't keep rows themselves, so add up the ones of all their leaf partitions.
+    // reltuples is -1 for tables never analyzed
+    let estimate_query = "WITH RECURSIVE root AS (
+            SELECT pgc.oid, pgc.relkind FROM pg_class pgc
+            JOIN pg_namespace pgn ON pgn.oid = pgc.relnamespace WHERE pgn.nspname = $1 AND pgc.relname = $2
+        ),
+        tree AS (
+            SELECT root.oid, root.relkind, 0 AS depth FROM root
+            UNION ALL
+            SELECT pgc.oid, pgc.relkind, tree.depth + 1 FROM tree
+            JOIN pg_inherits pgi ON pgi.inhparent = tree.oid
+            JOIN pg_class pgc ON pgc.oid = pgi.inhrelid
+            WHERE tree.relkind = 'p'
+        )
+        SELECT coalesce(sum(CASE WHEN tree.relkind <> 'p' THEN greatest(pgc.reltuples, 0) ELSE 0 END), 0)::bigint AS estimate,
+            pg_size_pretty(coalesce(sum(pg_total_relation_size(pgc.oid)), 0)::bigint) AS size,
+            sum(CASE WHEN tree.depth > 0 AND tree.relkind <> 'p' THEN 1 ELSE 0 END) AS partitions,
+            (SELECT pgp.relname::text FROM root JOIN pg_inherits pgi ON pgi.inhrelid = root.oid
+                JOIN pg_class pgp ON pgp.oid = pgi.inhparent WHERE pgp.relkind = 'r' ORDER BY pgi.inhseqno LIMIT 1) AS parent,
+            (SELECT count(*) FROM root JOIN pg_inherits pgi ON pgi.inhparent = root.oid WHERE root.relkind = 'r') AS children,
+            (SELECT relkind::text FROM root) AS kind
+        FROM tree JOIN pg_class pgc ON pgc.oid = tree.oid";
 
-    let total_rows:i64 = match count_db_client.query(estimate_query.as_str(), &[]) {
-        Ok(count) => count[0].get(0),
+    let row = match count_db_client.query_one(estimate_query, &[&schema, &table]) {
+        Ok(row) => row,
         Err(error) => { println!("Couldn't execute query: {} | Error: {} ", estimate_query, error); std::process::exit(1); }
-    };    
+    };
+    let total_rows:i64 = row.get(0);
+
+    let relkind:String = row.get(5);
+
+    TableStats { rows: total_rows as u64, size: row.get(1), partitions: row.get(2), parent: row.get(3), children: row.get(4),
+        kind: relkind_name(&relkind).to_string() }
+}
+
+fn relkind_name(relkind:&str) -> &str {
+    match relkind {
+        "p" => "partitioned table",
+        "v" => "view",
+        "m" => "materialized view",
+        "f" => "foreign table",
+        _ => "table"
+    }
+}
+
+// Get the leaf partitions of a table (empty if it's not partitioned), going down through partitions that are partitioned too
+pub fn get_partitions_of_table(schema:&str, table:&str) -> Vec<TablePartition> {
+    let mut partitions:Vec<TablePartition> = vec!();
+
+    // There are no partitions before PG 10
+    if !versions::source_supports(versions::PARTITIONS_VERSION) {
+        return partitions;
+    }
+
+    let mut client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let partitions_query = "WITH RECURSIVE tree AS (
+            SELECT pgc.oid, pgc.relkind FROM pg_class pgc
+            JOIN pg_namespace pgn ON pgn.oid = pgc.relnamespace WHERE pgn.nspname = $1 AND pgc.relname = $2 AND pgc.relkind = 'p'
+            UNION ALL
+            SELECT pgc.oid, pgc.relkind FROM tree
+            JOIN pg_inherits pgi ON pgi.inhparent = tree.oid
+            JOIN pg_class pgc ON pgc.oid = pgi.inhrelid
+            WHERE tree.relkind = 'p'
+        )
+        SELECT pgn.nspname::text, pgc.relname::text, pg_get_expr(pgc.relpartbound, pgc.oid), greatest(pgc.reltuples, 0)::bigint
+        FROM tree JOIN pg_class pgc ON pgc.oid = tree.oid JOIN pg_namespace pgn ON pgn.oid = pgc.relnamespace
+        WHERE tree.relkind <> 'p'
+        ORDER BY pgc.relname";
+
+    for row in client.query(partitions_query, &[&schema, &table]).unwrap() {
+        let rows:i64 = row.get(3);
+        partitions.push(TablePartition { schema: row.get(0), name: row.get(1), bound: row.get(2), rows: rows as u64 });
+    }
+
+    partitions
+}
+
+pub fn is_partitioned_table(db_url:&str, server_version:i32, schema:&str, table:&str) -> bool {
+    // There are no partitioned tables before PG 10
+    if server_version < versions::PARTITIONS_VERSION {
+        return false;
+    }
+
+    let mut client = match Client::connect(db_url, NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let partitioned_query = "SELECT pgc.relkind = 'p' FROM pg_class pgc
+        JOIN pg_namespace pgn ON pgn.oid = pgc.relnamespace WHERE pgn.nspname = $1 AND pgc.relname = $2";
+
+    match client.query(partitioned_query, &[&schema, &table]) {
+        Ok(rows) => rows.first().is_some_and(|row| row.get(0)),
+        Err(error) => { println!("Couldn't execute query: {} | Error: {} ", partitioned_query, error); std::process::exit(1); }
+    }
+}
+
+// Relation to read, delete or truncate only the rows of the table, and not the ones of the tables inheriting from it (legacy inheritance).
+// Partitioned tables don't have rows themselves, so their partitions are always included
+pub fn table_without_children(db_url:&str, server_version:i32, schema:&str, table:&str) -> String {
+    if is_partitioned_table(db_url, server_version, schema, table) {
+        return sql::qualified_table(schema, table);
+    }
+
+    sql::only_table(schema, table)
+}
+
+// Get all the tables inheriting from a table (legacy inheritance, not partitions), directly or through other child tables,
+// ordered by their depth in the hierarchy
+pub fn get_child_tables_of(schema:&str, table:&str) -> Vec<(String, String)> {
+    let mut client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let children_query = "WITH RECURSIVE tree AS (
+            SELECT pgc.oid, 0 AS depth FROM pg_class pgc
+            JOIN pg_namespace pgn ON pgn.oid = pgc.relnamespace WHERE pgn.nspname = $1 AND pgc.relname = $2 AND pgc.relkind = 'r'
+            UNION ALL
+            SELECT pgc.oid, tree.depth + 1 FROM tree
+            JOIN pg_inherits pgi ON pgi.inhparent = tree.oid
+            JOIN pg_class pgc ON pgc.oid = pgi.inhrelid
+            WHERE pgc.relkind = 'r'
+        )
+        SELECT pgn.nspname::text, pgc.relname::text, min(tree.depth) AS depth
+        FROM tree JOIN pg_class pgc ON pgc.oid = tree.oid JOIN pg_namespace pgn ON pgn.oid = pgc.relnamespace
+        WHERE tree.depth > 0
+        GROUP BY pgn.nspname, pgc.relname
+        ORDER BY depth, pgc.relname";
+
+    let mut children = vec!();
+    for row in client.query(children_query, &[&schema, &table]).unwrap() {
+        children.push((row.get(0), row.get(1)));
+    }
+
+    children
+}
 
-    return total_rows as u64
+// The table to import, followed by all its child tables if they must be imported too. Each of them is imported to its own target table,
+// with the same options but the partitions chosen for the parent table
+pub fn tables_to_import(schema:&str, table:&str, options:&ImportOptions) -> Vec<(String, String, ImportOptions)> {
+    let mut tables = vec!((schema.to_owned(), table.to_owned(), options.clone()));
+    if options.include_children {
+        let child_options = ImportOptions { partitions: vec!(), include_children: false, ..options.clone() };
+        for (child_schema, child_table) in get_child_tables_of(schema, table) {
+            tables.push((child_schema, child_table, child_options.clone()));
+        }
+    }
+
+    tables
+}
+
+// Partitions chosen to import from a partitioned table, all of them if none was chosen
+fn selected_partitions_of(schema:&str, table:&str, options:&ImportOptions) -> Vec<TablePartition> {
+    let partitions = get_partitions_of_table(schema, table);
+    if options.partitions.is_empty() {
+        return partitions;
+    }
+
+    partitions.into_iter().filter(|partition| options.partitions.contains(&partition.name)).collect()
+}
+
+// Check that the chosen partitions exist in the source table, and that they can be imported with the other options
+pub fn check_partitions(schema:&str, table:&str, options:&ImportOptions) {
+    if options.partitions.is_empty() {
+        return;
+    }
+
+    let partitions = get_partitions_of_table(schema, table);
+    if partitions.is_empty() {
+        println!("ERROR: Table {}.{} isn't partitioned. Can't select partitions to import from it", schema, table);
+        std::process::exit(1);
+    }
+
+    for partition_name in &options.partitions {
+        if !partitions.iter().any(|partition| &partition.name == partition_name) {
+            println!("ERROR: {} isn't a partition of table {}.{}", partition_name, schema, table);
+            std::process::exit(1);
+        }
+    }
+
+    if options.replace && !options.truncate {
+        println!("ERROR: Selecting partitions of {}.{} isn't supported in REPLACE mode. Use the WHERE clause instead", schema, table);
+        std::process::exit(1);
+    }
 }
 
 pub fn get_available_tables_in_schema(schema:&str) -> Vec<String> {
@@ -68,12 +357,19 @@ pub fn get_available_tables_in_schema(schema:&str) -> Vec<String> {
 
     let mut tables:Vec<String> = vec!();  
     
-    // Get all tables from the schema that aren't partitions
-    for row in client.query("select distinct table_name
-                from information_schema.tables ist
-                join pg_class pgc on ist.table_name = pgc.relname 
-                where ist.table_schema = $1 and ist.table_type = 'BASE TABLE'
-                and pgc.relispartition = false", &[&schema]).unwrap(){
+    // Get all tables, views, materialized views and foreign tables from the schema that aren't partitions (there are no partitions before PG 10)
+    let mut not_partition = "";
+    if versions::source_supports(versions::PARTITIONS_VERSION) {
+        not_partition = "and pgc.relispartition = false";
+    }
+    let tables_query = format!("select pgc.relname::text
+                from pg_class pgc
+                join pg_namespace pgn on pgn.oid = pgc.relnamespace
+                where pgn.nspname = $1 and pgc.relkind in ('r', 'p', 'v', 'm', 'f')
+                {}
+                order by pgc.relname", not_partition);
+
+    for row in client.query(tables_query.as_str(), &[&schema]).unwrap(){
         let table_name:String = row.try_get(0).unwrap();
         tables.push(table_name);
     }
@@ -89,7 +385,7 @@ pub fn get_any_unique_constraint_fields_for_table(schema:&str, table:&str) -> Op
 
     let unique_constraints = client.query(
         "select
-            string_agg(ccu.column_name, ', ') as constraint_columns
+            string_agg(quote_ident(ccu.column_name), ', ') as constraint_columns
         FROM
             INFORMATION_SCHEMA.TABLE_CONSTRAINTS AS tc
         JOIN INFORMATION_SCHEMA.CONSTRAINT_COLUMN_USAGE AS ccu ON ccu.CONSTRAINT_NAME = tc.CONSTRAINT_NAME
@@ -110,43 +406,518 @@ pub fn get_any_unique_constraint_fields_for_table(schema:&str, table:&str) -> Op
     }
 }
 
+// Primary key, unique, check and foreign key constraints of a table, with their definitions
+pub fn get_constraints_of_table(db_url:&str, schema:&str, table:&str) -> Vec<(String, String)> {
+    let mut client = match Client::connect(db_url, NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let constraints_query = "select pgco.conname::text, pg_get_constraintdef(pgco.oid)
+                from pg_constraint pgco
+                join pg_class pgc on pgc.oid = pgco.conrelid
+                join pg_namespace pgn on pgn.oid = pgc.relnamespace
+                where pgn.nspname = $1 and pgc.relname = $2 and pgco.contype in ('p', 'u', 'c', 'f')
+                order by pgco.conname";
+
+    client.query(constraints_query, &[&schema, &table]).unwrap().iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect()
+}
+
+pub fn get_columns_of_table(db_url:&str, server_version:i32, schema:&str, table:&str) -> Vec<TableColumn> {
+    let mut client = match Client::connect(db_url, NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let mut columns:Vec<TableColumn> = vec!();
+
+    // Older servers don't have generated or identity columns
+    let mut generated = "false";
+    if server_version >= versions::GENERATED_COLUMNS_VERSION {
+        generated = "pga.attgenerated <> ''";
+    }
+    let mut identity = "false";
+    if server_version >= versions::IDENTITY_COLUMNS_VERSION {
+        identity = "pga.attidentity <> ''";
+    }
+
+    // Read from the catalog, as information_schema doesn't include the columns of materialized views.
+    // Domains over oid (e.g. lo) are large objects too
+    let columns_query = format!("select pga.attname::text, {}, {}, coalesce(nullif(pgt.typbasetype, 0), pgt.oid) = 'oid'::regtype,
+                format_type(pga.atttypid, NULL), pga.attnotnull, pga.atthasdef, pgt.oid < {} and pgt.typelem < {},
+                case when pga.atttypid in ('varchar'::regtype, 'bpchar'::regtype) and pga.atttypmod > 4 then pga.atttypmod - 4 end
+                from pg_attribute pga
+                join pg_class pgc on pgc.oid = pga.attrelid
+                join pg_namespace pgn on pgn.oid = pgc.relnamespace
+                join pg_type pgt on pgt.oid = pga.atttypid
+                where pgn.nspname = $1 and pgc.relname = $2 and pga.attnum > 0 and not pga.attisdropped
+                order by pga.attnum", generated, identity, FIRST_USER_OID, FIRST_USER_OID);
+
+    for row in client.query(columns_query.as_str(), &[&schema, &table]).unwrap(){
+        columns.push(TableColumn { name: row.try_get(0).unwrap(), generated: row.try_get(1).unwrap(), identity: row.try_get(2).unwrap(),
+            large_object: row.try_get(3).unwrap(), data_type: row.try_get(4).unwrap(), not_null: row.try_get(5).unwrap(),
+            has_default: row.try_get(6).unwrap(), built_in_type: row.try_get(7).unwrap(), max_length: row.try_get(8).unwrap() });
+    }
+
+    columns
+}
+
+// Match source and target columns by name (after applying renames and exclusions), so the import doesn't depend on
+// the order of the columns in both tables. Source columns that don't exist in the target table are skipped.
+// Target columns with an expression get their values from it instead of from the source column with the same name.
+// Generated target columns are always skipped, and so are identity ones if their values must be generated by the target DB
+fn map_columns_by_name(schema:&str, table:&str, options:&ImportOptions) -> ColumnMapping {
+    let mut source_table_columns:Vec<String> = vec!();
+    let mut large_object_column_names:Vec<String> = vec!();
+    let mut source_types:HashMap<String, (String, bool)> = HashMap::new();
+    for column in get_source_columns(schema, table, options) {
+        if column.large_object && CONFIG_PROPERTIES.large_objects {
+            large_object_column_names.push(column.name.to_owned());
+        }
+        source_types.insert(column.name.to_owned(), (column.data_type, column.built_in_type));
+        source_table_columns.push(column.name);
+    }
+    let all_target_columns = get_columns_of_table(config::get_target_db_url().as_str(), *versions::TARGET_VERSION, schema, table);
+
+    if all_target_columns.is_empty() {
+        println!("ERROR: Table {}.{} doesn't exist in target DB", schema, table);
+        std::process::exit(1);
+    }
+
+    for renamed_column in options.renamed_columns.keys() {
+        if !source_table_columns.contains(renamed_column) {
+            println!("WARNING: Can't rename column {} as it doesn't exist in source table {}.{}", renamed_column, schema, table);
+        }
+    }
+
+    let generate_identity_values = CONFIG_PROPERTIES.identity_values == "TARGET";
+    let mut target_table_columns:Vec<String> = vec!();
+    let mut identity_columns:Vec<String> = vec!();
+    let mut skipped_columns:Vec<String> = vec!();
+    let mut target_types:HashMap<String, (String, bool)> = HashMap::new();
+    let mut target_max_lengths:HashMap<String, Option<i32>> = HashMap::new();
+    for column in all_target_columns {
+        target_max_lengths.insert(column.name.to_owned(), column.max_length);
+        target_types.insert(column.name.to_owned(), (column.data_type.to_owned(), column.built_in_type));
+        if column.generated {
+            println!("INFO: Column {} is generated in target table {}.{}. Skipping it", column.name, schema, table);
+            skipped_columns.push(column.name);
+        }
+        else if column.identity && generate_identity_values {
+            println!("INFO: Values for identity column {} will be generated by target table {}.{}", column.name, schema, table);
+            skipped_columns.push(column.name);
+        }
+        else {
+            if column.identity {
+                identity_columns.push(column.name.to_owned());
+            }
+            target_table_columns.push(column.name);
+        }
+    }
+
+    let mut source_columns = vec!();
+    let mut target_columns = vec!();
+    let mut large_object_columns = vec!();
+    let mut text_columns = vec!();
+
+    for source_column in source_table_columns {
+        if options.excluded_columns.contains(&source_column) {
+            continue;
+        }
+
+        let target_column = options.renamed_columns.get(&source_column).unwrap_or(&source_column).to_owned();
+        if options.column_expressions.contains_key(&target_column) {
+            continue;
+        }
+        else if target_table_columns.contains(&target_column) {
+            if large_object_column_names.contains(&source_column) {
+                println!("INFO: Large objects referenced by column {} will be copied to target DB", source_column);
+                large_object_columns.push(source_columns.len());
+            }
+            // Values in binary format are only valid for the same built-in type
+            let (target_type, target_built_in) = &target_types[&target_column];
+            let (source_type, source_built_in) = &source_types[&source_column];
+            if source_type != target_type || !source_built_in || !target_built_in {
+                text_columns.push((source_columns.len(), target_type.to_owned()));
+            }
+            source_columns.push(sql::quote_ident(&source_column));
+            target_columns.push(target_column);
+        }
+        else if !skipped_columns.contains(&target_column) {
+            println!("INFO: Column {} doesn't exist in target table {}.{}. Skipping it", target_column, schema, table);
+        }
+    }
+
+    let mut expression_columns:Vec<&String> = options.column_expressions.keys().collect();
+    expression_columns.sort();
+    for target_column in expression_columns {
+        if target_table_columns.contains(target_column) {
+            // The type of an expression is only known by the source DB
+            text_columns.push((source_columns.len(), target_types[target_column].0.to_owned()));
+            source_columns.push(options.column_expressions[target_column].to_owned());
+            target_columns.push(target_column.to_owned());
+        }
+        else {
+            println!("WARNING: Can't set column {} as it doesn't exist or isn't writable in target table {}.{}", target_column, schema, table);
+        }
+    }
+
+    if source_columns.is_empty() {
+        println!("ERROR: No columns to import from {}.{} after matching source and target columns", schema, table);
+        std::process::exit(1);
+    }
+
+    let overriding_system_value = target_columns.iter().any(|column| identity_columns.contains(column));
+
+    let masked_types:Vec<(String, Option<i32>)> = target_columns.iter()
+        .map(|column| (target_types[column].0.to_owned(), target_max_lengths[column])).collect();
+    let masked_columns = masking::masked_columns_of(schema, table, &target_columns, &masked_types, &options.masking_rules);
+
+    ColumnMapping { source_columns, target_columns, overriding_system_value, large_object_columns, masked_columns, text_columns }
+}
+
+// Check that the columns chosen to split the rows in chunks exist in the source table. Their values must be unique too
+// (otherwise some rows could be imported twice and others never), but that's too slow to check for big views
+pub fn check_chunk_key(schema:&str, table:&str, options:&ImportOptions) {
+    if options.chunk_key.is_empty() {
+        return;
+    }
+
+    let mut client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let explain_query = format!("EXPLAIN SELECT 1 FROM {} ORDER BY {}", source_relation_of(schema, table, options), sql::quote_idents(&options.chunk_key));
+    if let Err(error) = client.query(explain_query.as_str(), &[]) {
+        println!("ERROR: Invalid chunk key for table {}.{}: {} | Error: {}", schema, table, options.chunk_key.join(", "), error);
+        std::process::exit(1);
+    }
+}
+
+// Export a snapshot of the source DB, so several connections can read the same version of the data even if it's changed while importing.
+// The returned client keeps open the transaction that exported it, and must be kept until all connections have started using it
+pub fn export_source_snapshot(source_db_url:&str) -> (Option<Client>, Option<String>) {
+    if !versions::source_supports(versions::SNAPSHOT_EXPORT_VERSION) {
+        return (None, None);
+    }
+
+    let mut client = match Client::connect(source_db_url, NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+    client.batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ").unwrap();
+    let snapshot_id:String = client.query_one("SELECT pg_export_snapshot()", &[]).unwrap().get(0);
+
+    (Some(client), Some(snapshot_id))
+}
+
+pub fn use_source_snapshot(source_client:&mut Client, snapshot_id:&Option<String>) {
+    if let Some(snapshot_id) = snapshot_id {
+        source_client.batch_execute(format!("BEGIN ISOLATION LEVEL REPEATABLE READ; SET TRANSACTION SNAPSHOT '{}'",
+            snapshot_id).as_str()).unwrap();
+    }
+}
+
+// Check that the WHERE clause is valid for the table before starting any import, so a typo doesn't leave a job half done.
+// When replacing rows, the WHERE is also used in the target table
+pub fn check_where_clause(schema:&str, table:&str, options:&ImportOptions) {
+    // A query to import is always checked, even without a WHERE clause
+    if options.where_clause.is_empty() && options.query.is_empty() {
+        return;
+    }
+
+    let where_section = sql::where_section(&options.where_clause);
+    let mut explain_queries = vec!((config::get_source_db_url(),
+        format!("EXPLAIN SELECT 1 FROM {} {}", source_relation_of(schema, table, options), where_section)));
+    if options.replace && !options.truncate && !options.where_clause.is_empty() {
+        explain_queries.push((config::get_target_db_url(),
+            format!("EXPLAIN SELECT 1 FROM {} {}", sql::qualified_table(schema, table), where_section)));
+    }
+
+    for (db_url, explain_query) in explain_queries {
+        let mut client = match Client::connect(db_url.as_str(), NoTls) {
+            Ok(client) => client,
+            Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
+        };
+        filter::use_keys_tables(&mut client);
+
+        if let Err(error) = client.query(explain_query.as_str(), &[]) {
+            if options.query.is_empty() {
+                println!("ERROR: Invalid WHERE clause for table {}.{}: {} | Error: {}", schema, table, options.where_clause, error);
+            }
+            else {
+                println!("ERROR: Invalid query or WHERE clause to import into table {}.{}: {} | Error: {}", schema, table, explain_query, error);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+// Check the sample and limit of the rows to import, running them without reading any row
+pub fn check_sample(schema:&str, table:&str, options:&ImportOptions) {
+    if options.sample_percent.is_none() && options.limit.is_none() {
+        return;
+    }
+
+    if let Some(sample_percent) = options.sample_percent {
+        if !options.query.is_empty() {
+            println!("ERROR: The result of a query can't be sampled. Use TABLESAMPLE in the query instead");
+            std::process::exit(1);
+        }
+        if !versions::source_supports(versions::TABLESAMPLE_VERSION) {
+            println!("ERROR: Can't sample table {}.{}, as the source server doesn't support TABLESAMPLE", schema, table);
+            std::process::exit(1);
+        }
+        if sample_percent <= 0.0 || sample_percent > 100.0 {
+            println!("ERROR: Invalid sample percentage for table {}.{}: {}. It must be greater than 0 and up to 100", schema, table, sample_percent);
+            std::process::exit(1);
+        }
+    }
+    if options.limit.is_some_and(|limit| limit < 1) {
+        println!("ERROR: Invalid limit for table {}.{}: {}. It must be greater than 0", schema, table, options.limit.unwrap());
+        std::process::exit(1);
+    }
+
+    let mut client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+    filter::use_keys_tables(&mut client);
+    let explain_query = format!("EXPLAIN SELECT 1 FROM {}", sampled_relation(&source_relation_of(schema, table, options), options));
+    if let Err(error) = client.query(explain_query.as_str(), &[]) {
+        println!("ERROR: Invalid sample, order or limit for table {}.{}: {} | Error: {}", schema, table, explain_query, error);
+        std::process::exit(1);
+    }
+}
+
+// Only a sample (TABLESAMPLE) or the first rows in some order (ORDER BY ... LIMIT) of the relation, as a subquery with the same columns.
+// A sample always has a seed, so every chunk of a multi-thread import reads the same one
+fn sampled_relation(relation:&str, options:&ImportOptions) -> String {
+    if options.sample_percent.is_none() && options.limit.is_none() {
+        return relation.to_string();
+    }
+
+    let mut sample = format!("SELECT * FROM {}", relation);
+    if let Some(sample_percent) = options.sample_percent {
+        let mut sample_method = options.sample_method.to_uppercase();
+        if sample_method.is_empty() {
+            sample_method = String::from("BERNOULLI");
+        }
+        sample = format!("{} TABLESAMPLE {} ({}) REPEATABLE ({})", sample, sample_method, sample_percent, options.seed);
+    }
+    // The WHERE clause must select the rows before the limit
+    if !options.where_clause.is_empty() {
+        sample = format!("{} {}", sample, sql::where_section(&options.where_clause));
+    }
+    if !options.order_by.is_empty() {
+        sample = format!("{} ORDER BY {}", sample, options.order_by);
+    }
+    if let Some(limit) = options.limit {
+        sample = format!("{} LIMIT {}", sample, limit);
+    }
+
+    format!("({}) AS pgimporter_sample", sample)
+}
+
+// Relation the rows to import are read from in the source DB: the table with the same name or the query to import
+fn source_relation_of(schema:&str, table:&str, options:&ImportOptions) -> String {
+    if options.query.is_empty() {
+        return sql::qualified_table(schema, table);
+    }
+
+    sql::query_as_table(&options.query)
+}
+
+// Columns of the source table, or of the query to import into the table
+pub fn get_source_columns(schema:&str, table:&str, options:&ImportOptions) -> Vec<TableColumn> {
+    if options.query.is_empty() {
+        get_columns_of_table(config::get_source_db_url().as_str(), *versions::SOURCE_VERSION, schema, table)
+    }
+    else {
+        get_columns_of_query(config::get_source_db_url().as_str(), &options.query)
+    }
+}
+
+// Get the columns returned by a query, preparing it without running it
+fn get_columns_of_query(db_url:&str, query:&str) -> Vec<TableColumn> {
+    let mut client = match Client::connect(db_url, NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let statement = match client.prepare(format!("SELECT * FROM {}", sql::query_as_table(query)).as_str()) {
+        Ok(statement) => statement,
+        Err(error) => { println!("ERROR: Invalid query to import: {} | Error: {}", query, error); std::process::exit(1); }
+    };
+
+    // Nullability and defaults of the columns of a query are unknown
+    let mut columns = vec!();
+    for column in statement.columns() {
+        let type_row = client.query_one("SELECT format_type(oid, NULL), oid < $2 and typelem < $2 FROM pg_type WHERE oid = $1",
+            &[&column.type_().oid(), &FIRST_USER_OID]).unwrap();
+        columns.push(TableColumn { name: column.name().to_string(), generated: false, identity: false,
+            large_object: *column.type_() == Type::OID, data_type: type_row.get(0), not_null: false, has_default: false,
+            built_in_type: type_row.get(1), max_length: None });
+    }
+
+    columns
+}
+
+// TRUNCATE the target tables whose import requests it, all of them in the same statement, as Postgres only allows truncating
+// a table referenced by a foreign key along with the tables referencing it. With the given target client, inside the transaction
+// it already started
+pub fn truncate_target_tables(tables:&[&(String, String, ImportOptions)], target_client:Option<&mut Client>) {
+    let target_db_url = config::get_target_db_url();
+    let mut truncated_tables = vec!();
+    let mut cascade = false;
+
+    for (schema, table, options) in tables.iter().filter(|(_, _, options)| options.truncate) {
+        cascade = cascade || options.cascade;
+
+        // When only some partitions are imported, only the partitions with the same names are truncated in the target table
+        let mut tables_of_import = vec!((schema.to_owned(), table.to_owned()));
+        if !options.partitions.is_empty() {
+            tables_of_import = selected_partitions_of(schema, table, options).iter()
+                .map(|partition| (partition.schema.to_owned(), partition.name.to_owned()))
+                .collect();
+        }
+
+        for (truncated_schema, truncated_table) in tables_of_import {
+            if get_columns_of_table(target_db_url.as_str(), *versions::TARGET_VERSION, &truncated_schema, &truncated_table).is_empty() {
+                println!("ERROR: Can't TRUNCATE {}.{} as it doesn't exist in target DB", truncated_schema, truncated_table);
+                std::process::exit(1);
+            }
+            println!("TRUNCATING table {}.{}... {}", truncated_schema, truncated_table, if cascade { "CASCADE" } else { "" });
+            truncated_tables.push(table_without_children(target_db_url.as_str(), *versions::TARGET_VERSION, &truncated_schema, &truncated_table));
+        }
+    }
+    if truncated_tables.is_empty() {
+        return;
+    }
+
+    let mut truncate_query = format!("TRUNCATE TABLE {}", truncated_tables.join(", "));
+    if cascade {
+        truncate_query = format!("{} CASCADE", truncate_query);
+    }
+    let result = match target_client {
+        Some(target_client) => target_client.batch_execute(truncate_query.as_str()),
+        None => {
+            let mut target_client = match Client::connect(target_db_url.as_str(), NoTls) {
+                Ok(client) => client,
+                Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
+            };
+            target_client.batch_execute(truncate_query.as_str())
+        }
+    };
+    if let Err(error) = result {
+        println!("ERROR: Couldn't TRUNCATE target tables: {} | Error: {}", truncate_query, error);
+        std::process::exit(1);
+    }
+}
+
 // TODO: Pass here the connection params as a single struct
-pub fn import_table_from(schema:String, table:String, where_clause:String, truncate:bool, cascade: bool) {
+// Import the table, reading its rows from the given relation in the given order if any, instead of from the table,
+// and with the given target client, inside the transaction it already started
+pub fn import_table_from(schema:String, table:String, options:&ImportOptions, ordered_rows:Option<&(String, String)>,
+    target_client:Option<&mut Client>) {
     // Get some properties from config
     let source_db_url:String = config::get_source_db_url();
     let target_db_url:String = config::get_target_db_url();
-    let importer_impl = &CONFIG_PROPERTIES.importer_impl;
+    let mut importer_impl = CONFIG_PROPERTIES.importer_impl.to_owned();
 
-    let import_config = ImportConfig { schema: schema, table: table, where_clause: where_clause, 
-        source_db_url: source_db_url, target_db_url: target_db_url, importer_impl: importer_impl.to_string()};
+    // TRUNCATE already empties the whole target table, so there's nothing left to replace
+    let replace = options.replace && !options.truncate;
 
     println!();
-    println!("Importing table {}.{} ...", import_config.schema, import_config.table);
+    if options.query.is_empty() {
+        println!("Importing table {}.{} ...", schema, table);
+    }
+    else {
+        println!("Importing query into table {}.{} ...", schema, table);
+    }
+
+    let column_mapping = map_columns_by_name(&schema, &table, options);
+    // Values are masked in the stream of rows in COPY text format
+    if !column_mapping.masked_columns.is_empty() && importer_impl == "QUERY" {
+        println!("INFO: Masking columns of {}.{}. Switching to COPY importer", schema, table);
+        importer_impl = String::from("COPY");
+    }
+
+    let mut source_table = sql::query_as_table(&options.query);
+    if options.query.is_empty() {
+        source_table = table_without_children(source_db_url.as_str(), *versions::SOURCE_VERSION, &schema, &table);
+    }
+    let mut rows_order = String::new();
+    if let Some((ordered_relation, order)) = ordered_rows {
+        source_table = ordered_relation.to_owned();
+        rows_order = order.to_owned();
+    }
+    let mut source_where_clause = options.where_clause.to_owned();
+    if options.sample_percent.is_some() || options.limit.is_some() {
+        source_where_clause = String::new();
+    }
+    source_table = sampled_relation(&source_table, options);
+    let target_table = table_without_children(target_db_url.as_str(), *versions::TARGET_VERSION, &schema, &table);
+    let in_transaction = replace || target_client.is_some();
+    let import_config = ImportConfig { schema, table, source_table, target_table, where_clause: options.where_clause.to_owned(),
+        source_where_clause, rows_order, replace, in_transaction,
+        source_columns: column_mapping.source_columns, target_columns: column_mapping.target_columns,
+        overriding_system_value: column_mapping.overriding_system_value, large_object_columns: column_mapping.large_object_columns,
+        masked_columns: column_mapping.masked_columns, text_columns: column_mapping.text_columns, source_db_url, target_db_url, importer_impl};
 
     // Start measuring total time spent importing this table
     let start = Instant::now();
+    let rejected_rows_before = rejects::rejected_rows();
 
-    // TRUNCATE target table if truncate is requested
-    if truncate {
-        let mut cascade_str = "";
-        if cascade { cascade_str = "CASCADE" }
-        println!("TRUNCATING table {}.{}... {}", import_config.schema, import_config.table, cascade_str);
-        let mut target_client = match Client::connect(import_config.target_db_url.as_ref(), NoTls) {
-            Ok(client) => client,
-            Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
-        };
+    // Partitioned tables are imported reading each partition on its own. In a transaction (REPLACE mode) they're read as a whole table,
+    // as the DELETE and the load must share it, and so they are when sampled, as the sample is of the whole table, or loaded in order
+    let mut partitions = vec!();
+    if !import_config.in_transaction && options.query.is_empty() && options.sample_percent.is_none() && options.limit.is_none()
+        && ordered_rows.is_none() {
+        partitions = selected_partitions_of(&import_config.schema, &import_config.table, options);
+    }
+
+    let total_rows_to_import;
 
-        let mut truncate_query = format!("TRUNCATE TABLE {}.{}", import_config.schema, import_config.table);
-        if cascade {
-            truncate_query = format!("{} CASCADE", truncate_query);
+    if !partitions.is_empty() {
+        // Rows are read from each partition and written to the target table, which routes them to its own partitions
+        let mut partition_imports = vec!();
+        for partition in &partitions {
+            let mut partition_config = import_config.clone();
+            partition_config.source_table = sql::qualified_table(&partition.schema, &partition.name);
+            let partition_rows = count_total_rows_for_import(&partition_config);
+            partition_imports.push((partition_config, partition_rows));
         }
-        target_client.execute(truncate_query.as_str(), &[]).unwrap();
+        total_rows_to_import = partition_imports.iter().map(|(_, partition_rows)| partition_rows).sum();
+
+        println!("{} rows to insert in total from {} partitions", total_rows_to_import, partition_imports.len());
+        partition_import::partitioned_import(partition_imports);
     }
+    else {
+        total_rows_to_import = count_total_rows_for_import(&import_config);
+        println!("{} rows to insert in total", total_rows_to_import);
+        match target_client {
+            // The other tables loaded in the same transaction wait for this one, so there's no point in splitting it between threads
+            Some(target_client) => single_import::import_rows_with(&import_config, target_client, total_rows_to_import as u64),
+            None => import_whole_table(import_config.clone(), options, total_rows_to_import)
+        }
+    }
+
+    let duration = start.elapsed();
+    println!("Finished importing {} rows from table {}.{} in {} secs", total_rows_to_import, import_config.schema, 
+        import_config.table, duration.as_secs());
 
-    let total_rows_to_import = count_total_rows_for_import(&import_config);
+    let rejected_rows = rejects::rejected_rows() - rejected_rows_before;
+    if rejected_rows > 0 {
+        println!("WARNING: {} rows from table {}.{} were rejected by target DB. Check them in {}", rejected_rows,
+            import_config.schema, import_config.table, CONFIG_PROPERTIES.reject_filename);
+    }
+}
 
-    println!("{} rows to insert in total", total_rows_to_import);
-    
+fn import_whole_table(import_config:ImportConfig, options:&ImportOptions, total_rows_to_import:i64) {
     // Use smart pointers to share the same common Boxed values between all potential Threads (not needed for unboxed types)
     let import_config = Arc::new(import_config);
 
@@ -154,11 +925,47 @@ pub fn import_table_from(schema:String, table:String, where_clause:String, trunc
     if CONFIG_PROPERTIES.max_threads < 2 {
         single_import::single_thread_import(&import_config, total_rows_to_import as u64);
     }
+    // The DELETE and the load must share the same transaction, so they can't be split between threads
+    else if import_config.replace {
+        println!("INFO: Replacing rows of {}.{} in a single transaction. Switching to SINGLE Thread import",
+            &import_config.schema, &import_config.table);
+        single_import::single_thread_import(&import_config, total_rows_to_import as u64);
+    }
+    else if !import_config.rows_order.is_empty() {
+        println!("INFO: Loading the rows of {}.{} referenced by other rows of the table first. Switching to SINGLE Thread import",
+            &import_config.schema, &import_config.table);
+        single_import::single_thread_import(&import_config, total_rows_to_import as u64);
+    }
+    // Rows with the same values in the ORDER BY could be picked differently by each chunk
+    else if let Some(limit) = options.limit {
+        println!("INFO: Importing the first {} rows of {}.{}. Switching to SINGLE Thread import", limit,
+            &import_config.schema, &import_config.table);
+        single_import::single_thread_import(&import_config, total_rows_to_import as u64);
+    }
+    // Views, materialized views and foreign tables don't have UNIQUE constraints, but the user can choose the columns to order by
+    else if !options.chunk_key.is_empty() {
+        multi_import::multi_thread_import(&import_config, &sql::quote_idents(&options.chunk_key), total_rows_to_import);
+    }
+    else if !options.query.is_empty() {
+        println!("INFO: Rows of the query can't be split between threads without a chunk key. Switching to SINGLE Thread import");
+        single_import::single_thread_import(&import_config, total_rows_to_import as u64);
+    }
     else {
         // Check if there's any UNIQUE constraint in the source table so we can use it for the ORDER BY
         // If there's none we have to use single-thread version to make import results are correct
         match get_any_unique_constraint_fields_for_table(&import_config.schema, &import_config.table) {
             Some(order_by) => multi_import::multi_thread_import(&import_config, &order_by, total_rows_to_import),
+            // Rows with the same time are ordered by their physical location, which doesn't change in the snapshot shared by all threads.
+            // Only tables have it, and not the sample of a table. Tables imported in full may not have the time column
+            None if !options.time_column.is_empty() && options.sample_percent.is_none()
+                && ["table", "partitioned table"].contains(&get_table_stats_for(&import_config.schema, &import_config.table).kind.as_str())
+                && get_source_columns(&import_config.schema, &import_config.table, options).iter()
+                    .any(|source_column| source_column.name == options.time_column) => {
+                println!("INFO: {}.{} doesn't have any UNIQUE constraint to order by. Splitting its rows between threads by time column {}",
+                    &import_config.schema, &import_config.table, options.time_column);
+                let order_by = format!("{}, tableoid, ctid", sql::quote_ident(&options.time_column));
+                multi_import::multi_thread_import(&import_config, &order_by, total_rows_to_import);
+            },
             None => {
                 println!("INFO: {}.{} doesn't have any UNIQUE constraint to order by. Switching to SINGLE Thread import", 
                     &import_config.schema, &import_config.table);
@@ -166,10 +973,6 @@ pub fn import_table_from(schema:String, table:String, where_clause:String, trunc
             }
         }
     }
-
-    let duration = start.elapsed();
-    println!("Finished importing {} rows from table {}.{} in {} secs", total_rows_to_import, import_config.schema, 
-        import_config.table, duration.as_secs());
 }
 
 
@@ -178,12 +981,11 @@ fn count_total_rows_for_import(import_config:&ImportConfig) -> i64 {
         Ok(client) => client,
         Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
     };
+    filter::use_keys_tables(&mut count_db_client);
     
     // Count the rows to import
-    let mut count_query = format!("SELECT count(1) FROM {}.{}", import_config.schema, import_config.table);
-    if !import_config.where_clause.is_empty() {
-        count_query = format!("{} WHERE {}", count_query, import_config.where_clause)
-    }
+    let count_query = format!("SELECT count(1) FROM {} {}", import_config.source_table,
+        sql::where_section(&import_config.source_where_clause));
 
     let total_rows_to_import:i64 = match count_db_client.query(count_query.as_str(), &[]) {
         Ok(count) => count[0].get(0),
@@ -197,3 +999,29 @@ fn count_total_rows_for_import(import_config:&ImportConfig) -> i64 {
 
     total_rows_to_import
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampled_relation_without_sample_is_the_relation() {
+        let options = ImportOptions { where_clause: String::from("id > 10"), ..Default::default() };
+        assert_eq!(sampled_relation("\"public\".\"t\"", &options), "\"public\".\"t\"");
+    }
+
+    #[test]
+    fn sampled_relation_applies_the_where_clause_inside() {
+        let options = ImportOptions { where_clause: String::from("id > 10"), sample_percent: Some(5.0), seed: 42, ..Default::default() };
+        assert_eq!(sampled_relation("\"public\".\"t\"", &options),
+            "(SELECT * FROM \"public\".\"t\" TABLESAMPLE BERNOULLI (5) REPEATABLE (42) WHERE id > 10) AS pgimporter_sample");
+    }
+
+    #[test]
+    fn sampled_relation_limits_the_rows_after_the_where_clause() {
+        let options = ImportOptions { where_clause: String::from("id > 10"), order_by: String::from("id DESC"), limit: Some(100),
+            ..Default::default() };
+        assert_eq!(sampled_relation("\"public\".\"t\"", &options),
+            "(SELECT * FROM \"public\".\"t\" WHERE id > 10 ORDER BY id DESC LIMIT 100) AS pgimporter_sample");
+    }
+}
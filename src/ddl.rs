@@ -0,0 +1,525 @@
+use postgres::{Client, NoTls};
+use std::collections::HashSet;
+
+use crate::config;
+use crate::config::ImportOptions;
+use crate::db;
+use crate::sql;
+use crate::versions;
+
+// Temporary view created in the source DB to read the columns returned by a query to import
+const QUERY_VIEW:&str = "pg_temp.pgimporter_query";
+
+// Definition of a column in the source DB, to create it in the target DB
+struct ColumnDefinition {
+    name:String,
+    data_type:String,
+    default:Option<String>,
+    not_null:bool,
+    // 'a' (ALWAYS) or 'd' (BY DEFAULT) for identity columns
+    identity:String,
+    // 's' for generated (stored) columns, whose expression is in default
+    generated:String,
+    // Sequence owned by a serial column, which must exist before the column is created
+    serial_sequence:Option<String>
+}
+
+enum ObjectKind {
+    Extension,
+    Type,
+    Sequence,
+    View
+}
+
+// Non-table object of the source DB needed by the tables to import, and the statements to create it in the target DB
+struct DependentObject {
+    kind:ObjectKind,
+    // e.g. "enum type public.mood"
+    description:String,
+    schema:String,
+    name:String,
+    // Empty when pgimporter doesn't know how to create it (e.g. base or range types)
+    statements:Vec<String>
+}
+
+// Create in the target DB everything the tables to import need and doesn't exist there yet, before any data is copied:
+// the types and sequences used by their columns, the tables themselves (or their missing columns), and the views over them
+pub fn create_missing_objects(tables:&[(String, String, ImportOptions)]) {
+    let tables_to_create:Vec<&(String, String, ImportOptions)> = tables.iter()
+        .filter(|(_, _, options)| options.create_missing || options.add_missing_columns)
+        .collect();
+    if tables_to_create.is_empty() {
+        return;
+    }
+
+    let mut source_client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    // Types and sequences, each one after the ones it depends on
+    let mut dependencies = vec!();
+    let mut visited_types = HashSet::new();
+    for (schema, table, options) in &tables_to_create {
+        let source_relation = source_relation_for(&mut source_client, schema, table, options);
+        add_type_dependencies_of(&mut source_client, &source_relation, &mut dependencies, &mut visited_types);
+        add_sequence_dependencies_of(&mut source_client, &source_relation, &mut dependencies);
+    }
+    create_objects(&dependencies);
+
+    for (schema, table, options) in &tables_to_create {
+        create_missing_table(schema, table, options);
+    }
+
+    // Views can only be created once all the tables they read exist
+    let mut views = vec!();
+    for (schema, table, options) in &tables_to_create {
+        if options.create_missing && options.query.is_empty() {
+            add_views_over(&mut source_client, &sql::qualified_table(schema, table), &mut views);
+        }
+    }
+    create_objects(&views);
+}
+
+// Create the target table if it doesn't exist, or add the columns it lacks, as defined in the source DB
+fn create_missing_table(schema:&str, table:&str, options:&ImportOptions) {
+    let target_columns = db::get_columns_of_table(config::get_target_db_url().as_str(), *versions::TARGET_VERSION, schema, table);
+
+    if target_columns.is_empty() && options.create_missing {
+        if config::is_dry_run() {
+            println!("DRY RUN: Would create table {}.{}", schema, table);
+            return;
+        }
+        create_table(schema, table, options);
+    }
+    else if !target_columns.is_empty() && options.add_missing_columns {
+        let target_column_names:Vec<String> = target_columns.into_iter().map(|column| column.name).collect();
+        add_missing_columns(schema, table, options, &target_column_names);
+    }
+}
+
+fn create_table(schema:&str, table:&str, options:&ImportOptions) {
+    let mut source_client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+    let source_relation = source_relation_for(&mut source_client, schema, table, options);
+    let columns = target_column_definitions(&mut source_client, &source_relation, options);
+
+    let mut statements = vec!(format!("CREATE SCHEMA IF NOT EXISTS {}", sql::quote_ident(schema)));
+
+    let mut column_definitions = vec!();
+    let mut owned_sequences = vec!();
+    for column in &columns {
+        let mut definition = format!("{} {}", sql::quote_ident(&column.name), column.data_type);
+        if column.generated == "s" {
+            definition = format!("{} GENERATED ALWAYS AS ({}) STORED", definition, column.default.to_owned().unwrap_or_default());
+        }
+        else if column.identity == "a" {
+            definition = format!("{} GENERATED ALWAYS AS IDENTITY", definition);
+        }
+        else if column.identity == "d" {
+            definition = format!("{} GENERATED BY DEFAULT AS IDENTITY", definition);
+        }
+        else if let Some(default) = &column.default {
+            definition = format!("{} DEFAULT {}", definition, default);
+        }
+        if column.not_null {
+            definition = format!("{} NOT NULL", definition);
+        }
+        column_definitions.push(definition);
+
+        if let Some(sequence) = &column.serial_sequence {
+            statements.push(format!("CREATE SEQUENCE IF NOT EXISTS {}", sequence));
+            owned_sequences.push(format!("ALTER SEQUENCE {} OWNED BY {}.{}", sequence, sql::qualified_table(schema, table),
+                sql::quote_ident(&column.name)));
+        }
+    }
+
+    statements.push(format!("CREATE TABLE {} (\n    {}\n)", sql::qualified_table(schema, table), column_definitions.join(",\n    ")));
+    statements.extend(owned_sequences);
+
+    // Keys and indexes can't be created when their columns are renamed or excluded
+    if options.renamed_columns.is_empty() && options.excluded_columns.is_empty() {
+        statements.extend(constraint_statements(&mut source_client, &source_relation, schema, table));
+        statements.extend(index_statements(&mut source_client, &source_relation));
+    }
+    else {
+        println!("INFO: Keys and indexes of {}.{} won't be created, as some of its columns are renamed or excluded", schema, table);
+    }
+
+    // All or nothing, so a failing statement doesn't leave a half created table
+    let mut target_client = match Client::connect(config::get_target_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
+    };
+    let mut transaction = target_client.transaction().unwrap();
+    for statement in &statements {
+        if let Err(error) = transaction.batch_execute(statement) {
+            println!("ERROR: Couldn't create table {}.{} in target DB: {} | Error: {}", schema, table, statement, error);
+            std::process::exit(1);
+        }
+    }
+    transaction.commit().unwrap();
+
+    println!("INFO: Created table {}.{} in target DB", schema, table);
+}
+
+fn add_missing_columns(schema:&str, table:&str, options:&ImportOptions, target_column_names:&[String]) {
+    let mut source_client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+    let source_relation = source_relation_for(&mut source_client, schema, table, options);
+
+    let mut target_client = match Client::connect(config::get_target_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
+    };
+    for column in target_column_definitions(&mut source_client, &source_relation, options) {
+        if target_column_names.contains(&column.name) {
+            continue;
+        }
+        if config::is_dry_run() {
+            println!("DRY RUN: Would add column {} to target table {}.{}", column.name, schema, table);
+            continue;
+        }
+
+        // Only the type, as the target table may already have rows that wouldn't fit a NOT NULL or a default using other objects
+        let add_column = format!("ALTER TABLE {} ADD COLUMN {} {}", sql::qualified_table(schema, table), sql::quote_ident(&column.name),
+            column.data_type);
+        if let Err(error) = target_client.batch_execute(&add_column) {
+            println!("ERROR: Couldn't add column {} to target table {}.{}: {} | Error: {}", column.name, schema, table, add_column, error);
+            std::process::exit(1);
+        }
+        println!("INFO: Added column {} to target table {}.{}", column.name, schema, table);
+    }
+}
+
+// Name of the relation in the source DB with the columns to create, usable as a regclass
+fn source_relation_for(source_client:&mut Client, schema:&str, table:&str, options:&ImportOptions) -> String {
+    if options.query.is_empty() {
+        return sql::qualified_table(schema, table);
+    }
+
+    // A view of the query keeps the exact types of its columns (e.g. numeric(10,2))
+    let create_view = format!("CREATE OR REPLACE TEMPORARY VIEW pgimporter_query AS {}", options.query.trim().trim_end_matches(';'));
+    if let Err(error) = source_client.batch_execute(&create_view) {
+        println!("ERROR: Invalid query to import: {} | Error: {}", options.query, error);
+        std::process::exit(1);
+    }
+
+    QUERY_VIEW.to_string()
+}
+
+// Source columns with the names they'll have in the target table
+fn target_column_definitions(source_client:&mut Client, source_relation:&str, options:&ImportOptions) -> Vec<ColumnDefinition> {
+    get_column_definitions(source_client, source_relation).into_iter()
+        .filter(|column| !options.excluded_columns.contains(&column.name))
+        .map(|mut column| {
+            if let Some(target_name) = options.renamed_columns.get(&column.name) {
+                column.name = target_name.to_owned();
+            }
+            column
+        })
+        .collect()
+}
+
+fn get_column_definitions(source_client:&mut Client, source_relation:&str) -> Vec<ColumnDefinition> {
+    // Older servers don't have generated or identity columns
+    let mut identity = "''";
+    if versions::source_supports(versions::IDENTITY_COLUMNS_VERSION) {
+        identity = "pga.attidentity::text";
+    }
+    let mut generated = "''";
+    if versions::source_supports(versions::GENERATED_COLUMNS_VERSION) {
+        generated = "pga.attgenerated::text";
+    }
+
+    let columns_query = format!("SELECT pga.attname::text, format_type(pga.atttypid, pga.atttypmod), pg_get_expr(pgd.adbin, pgd.adrelid),
+                pga.attnotnull, {}, {}, pg_get_serial_sequence($1, pga.attname)
+            FROM pg_attribute pga
+            LEFT JOIN pg_attrdef pgd ON pgd.adrelid = pga.attrelid AND pgd.adnum = pga.attnum
+            WHERE pga.attrelid = $1::text::regclass AND pga.attnum > 0 AND NOT pga.attisdropped
+            ORDER BY pga.attnum", identity, generated);
+
+    let mut columns = vec!();
+    for row in source_client.query(columns_query.as_str(), &[&source_relation]).unwrap() {
+        let identity:String = row.get(4);
+        let mut serial_sequence:Option<String> = row.get(6);
+        // Sequences of identity columns are created along with them
+        if !identity.is_empty() {
+            serial_sequence = None;
+        }
+        columns.push(ColumnDefinition { name: row.get(0), data_type: row.get(1), default: row.get(2), not_null: row.get(3),
+            identity, generated: row.get(5), serial_sequence });
+    }
+
+    columns
+}
+
+// Primary keys and unique constraints
+fn constraint_statements(source_client:&mut Client, source_relation:&str, schema:&str, table:&str) -> Vec<String> {
+    let constraints_query = "SELECT conname::text, pg_get_constraintdef(oid) FROM pg_constraint
+        WHERE conrelid = $1::text::regclass AND contype IN ('p', 'u')
+        ORDER BY contype, conname";
+
+    source_client.query(constraints_query, &[&source_relation]).unwrap().iter()
+        .map(|row| {
+            let name:String = row.get(0);
+            let definition:String = row.get(1);
+            format!("ALTER TABLE {} ADD CONSTRAINT {} {}", sql::qualified_table(schema, table), sql::quote_ident(&name), definition)
+        })
+        .collect()
+}
+
+// Indexes that aren't created by a constraint. Their definitions already include the schema and name of the table
+fn index_statements(source_client:&mut Client, source_relation:&str) -> Vec<String> {
+    let indexes_query = "SELECT pg_get_indexdef(pgi.indexrelid) FROM pg_index pgi
+        WHERE pgi.indrelid = $1::text::regclass
+        AND NOT EXISTS (SELECT 1 FROM pg_constraint pgc WHERE pgc.conindid = pgi.indexrelid)
+        ORDER BY pgi.indexrelid";
+
+    source_client.query(indexes_query, &[&source_relation]).unwrap().iter()
+        .map(|row| row.get(0))
+        .collect()
+}
+
+// Types of the columns of the relation that aren't built-in, after the types they are based on
+fn add_type_dependencies_of(source_client:&mut Client, source_relation:&str, objects:&mut Vec<DependentObject>, visited:&mut HashSet<u32>) {
+    let types_query = "SELECT atttypid FROM pg_attribute WHERE attrelid = $1::text::regclass AND attnum > 0 AND NOT attisdropped ORDER BY attnum";
+
+    let type_oids:Vec<u32> = source_client.query(types_query, &[&source_relation]).unwrap().iter().map(|row| row.get(0)).collect();
+    for type_oid in type_oids {
+        add_type(source_client, type_oid, objects, visited);
+    }
+}
+
+fn add_type(source_client:&mut Client, type_oid:u32, objects:&mut Vec<DependentObject>, visited:&mut HashSet<u32>) {
+    if !visited.insert(type_oid) {
+        return;
+    }
+
+    // Types of extensions are created by their extension, which pg_depend links them to
+    let type_query = "SELECT pgn.nspname::text, pgt.typname::text, pgt.typtype::text, pgt.typcategory::text, pgt.typelem, pgt.typbasetype,
+            COALESCE(pgc.relkind::text, ''),
+            (SELECT pge.extname::text FROM pg_depend pgd JOIN pg_extension pge ON pge.oid = pgd.refobjid
+                WHERE pgd.classid = 'pg_type'::regclass AND pgd.objid = pgt.oid AND pgd.refclassid = 'pg_extension'::regclass AND pgd.deptype = 'e')
+        FROM pg_type pgt
+        JOIN pg_namespace pgn ON pgn.oid = pgt.typnamespace
+        LEFT JOIN pg_class pgc ON pgc.oid = pgt.typrelid
+        WHERE pgt.oid = $1";
+    let row = source_client.query_one(type_query, &[&type_oid]).unwrap();
+    let schema:String = row.get(0);
+    let name:String = row.get(1);
+    let type_type:String = row.get(2);
+    let category:String = row.get(3);
+    let element_type:u32 = row.get(4);
+    let base_type:u32 = row.get(5);
+    let relkind:String = row.get(6);
+    let extension:Option<String> = row.get(7);
+
+    // Arrays are created along with their element type
+    if category == "A" && element_type != 0 {
+        add_type(source_client, element_type, objects, visited);
+        return;
+    }
+    if schema == "pg_catalog" || schema == "information_schema" {
+        return;
+    }
+
+    let qualified_name = sql::qualified_table(&schema, &name);
+    if let Some(extension) = extension {
+        if !objects.iter().any(|object| matches!(object.kind, ObjectKind::Extension) && object.name == extension) {
+            objects.push(DependentObject { kind: ObjectKind::Extension, description: format!("extension {}", extension), schema: schema.to_owned(),
+                name: extension.to_owned(),
+                statements: vec!(format!("CREATE EXTENSION IF NOT EXISTS {} WITH SCHEMA {}", sql::quote_ident(&extension), sql::quote_ident(&schema)))});
+        }
+        return;
+    }
+
+    let (description, statements) = match type_type.as_str() {
+        "e" => {
+            let labels_query = "SELECT quote_literal(enumlabel) FROM pg_enum WHERE enumtypid = $1 ORDER BY enumsortorder";
+            let labels:Vec<String> = source_client.query(labels_query, &[&type_oid]).unwrap().iter().map(|row| row.get(0)).collect();
+            (format!("enum type {}.{}", schema, name), vec!(format!("CREATE TYPE {} AS ENUM ({})", qualified_name, labels.join(", "))))
+        },
+        "d" => {
+            add_type(source_client, base_type, objects, visited);
+            (format!("domain {}.{}", schema, name), vec!(create_domain_statement(source_client, type_oid, &qualified_name)))
+        },
+        // Row types of tables can't be created on their own
+        "c" if relkind == "c" => {
+            let attributes_query = "SELECT pga.attname::text, pga.atttypid, format_type(pga.atttypid, pga.atttypmod)
+                FROM pg_attribute pga JOIN pg_type pgt ON pgt.typrelid = pga.attrelid
+                WHERE pgt.oid = $1 AND pga.attnum > 0 AND NOT pga.attisdropped
+                ORDER BY pga.attnum";
+            let mut attributes = vec!();
+            for attribute in source_client.query(attributes_query, &[&type_oid]).unwrap() {
+                add_type(source_client, attribute.get(1), objects, visited);
+                let attribute_name:String = attribute.get(0);
+                let attribute_type:String = attribute.get(2);
+                attributes.push(format!("{} {}", sql::quote_ident(&attribute_name), attribute_type));
+            }
+            (format!("composite type {}.{}", schema, name), vec!(format!("CREATE TYPE {} AS ({})", qualified_name, attributes.join(", "))))
+        },
+        _ => (format!("type {}.{}", schema, name), vec!())
+    };
+
+    objects.push(DependentObject { kind: ObjectKind::Type, description, schema, name, statements });
+}
+
+fn create_domain_statement(source_client:&mut Client, type_oid:u32, qualified_name:&str) -> String {
+    let domain_query = "SELECT format_type(typbasetype, typtypmod), typdefault, typnotnull FROM pg_type WHERE oid = $1";
+    let row = source_client.query_one(domain_query, &[&type_oid]).unwrap();
+    let base_type:String = row.get(0);
+    let default:Option<String> = row.get(1);
+    let not_null:bool = row.get(2);
+
+    let mut statement = format!("CREATE DOMAIN {} AS {}", qualified_name, base_type);
+    if let Some(default) = default {
+        statement = format!("{} DEFAULT {}", statement, default);
+    }
+    if not_null {
+        statement = format!("{} NOT NULL", statement);
+    }
+
+    let constraints_query = "SELECT conname::text, pg_get_constraintdef(oid) FROM pg_constraint WHERE contypid = $1 ORDER BY conname";
+    for constraint in source_client.query(constraints_query, &[&type_oid]).unwrap() {
+        let constraint_name:String = constraint.get(0);
+        let definition:String = constraint.get(1);
+        statement = format!("{} CONSTRAINT {} {}", statement, sql::quote_ident(&constraint_name), definition);
+    }
+
+    statement
+}
+
+// Sequences used by the defaults of the columns of the relation (e.g. serial columns), starting at the value they have in the source DB
+fn add_sequence_dependencies_of(source_client:&mut Client, source_relation:&str, objects:&mut Vec<DependentObject>) {
+    let sequences_query = "SELECT DISTINCT pgn.nspname::text, pgc.relname::text, pgc.oid
+        FROM pg_attrdef pgad
+        JOIN pg_depend pgd ON pgd.classid = 'pg_attrdef'::regclass AND pgd.objid = pgad.oid AND pgd.refclassid = 'pg_class'::regclass
+        JOIN pg_class pgc ON pgc.oid = pgd.refobjid AND pgc.relkind = 'S'
+        JOIN pg_namespace pgn ON pgn.oid = pgc.relnamespace
+        WHERE pgad.adrelid = $1::text::regclass
+        ORDER BY pgc.oid";
+
+    for row in source_client.query(sequences_query, &[&source_relation]).unwrap() {
+        let schema:String = row.get(0);
+        let name:String = row.get(1);
+        let sequence_oid:u32 = row.get(2);
+        if objects.iter().any(|object| matches!(object.kind, ObjectKind::Sequence) && object.schema == schema && object.name == name) {
+            continue;
+        }
+
+        let qualified_name = sql::qualified_table(&schema, &name);
+        let mut create_sequence = format!("CREATE SEQUENCE {}", qualified_name);
+        if versions::source_supports(versions::SEQUENCE_CATALOG_VERSION) {
+            let sequence_query = "SELECT format_type(seqtypid, NULL), seqincrement, seqmin, seqmax, seqstart, seqcache, seqcycle
+                FROM pg_sequence WHERE seqrelid = $1";
+            let sequence = source_client.query_one(sequence_query, &[&sequence_oid]).unwrap();
+            // Older targets only have bigint sequences
+            if versions::target_supports(versions::SEQUENCE_CATALOG_VERSION) {
+                let data_type:String = sequence.get(0);
+                create_sequence = format!("{} AS {}", create_sequence, data_type);
+            }
+            let (increment, min, max, start, cache):(i64, i64, i64, i64, i64) = (sequence.get(1), sequence.get(2), sequence.get(3),
+                sequence.get(4), sequence.get(5));
+            let cycle:bool = sequence.get(6);
+            create_sequence = format!("{} INCREMENT BY {} MINVALUE {} MAXVALUE {} START WITH {} CACHE {}{}", create_sequence, increment, min, max,
+                start, cache, if cycle { " CYCLE" } else { "" });
+        }
+
+        let value = source_client.query_one(format!("SELECT last_value, is_called FROM {}", qualified_name).as_str(), &[]).unwrap();
+        let last_value:i64 = value.get(0);
+        let is_called:bool = value.get(1);
+        let set_value = format!("SELECT setval('{}', {}, {})", qualified_name.replace('\'', "''"), last_value, is_called);
+
+        objects.push(DependentObject { kind: ObjectKind::Sequence, description: format!("sequence {}.{}", schema, name), schema, name,
+            statements: vec!(create_sequence, set_value) });
+    }
+}
+
+// Views reading the table, and the views reading those views, each one after the ones it reads
+fn add_views_over(source_client:&mut Client, source_table:&str, objects:&mut Vec<DependentObject>) {
+    let views_query = "SELECT DISTINCT pgc.oid, pgn.nspname::text, pgc.relname::text, pg_get_viewdef(pgc.oid)
+        FROM pg_depend pgd
+        JOIN pg_rewrite pgr ON pgr.oid = pgd.objid
+        JOIN pg_class pgc ON pgc.oid = pgr.ev_class AND pgc.relkind = 'v'
+        JOIN pg_namespace pgn ON pgn.oid = pgc.relnamespace
+        WHERE pgd.classid = 'pg_rewrite'::regclass AND pgd.refclassid = 'pg_class'::regclass
+        AND pgd.refobjid = $1::text::regclass AND pgc.oid <> $1::text::regclass
+        ORDER BY pgc.oid";
+
+    for row in source_client.query(views_query, &[&source_table]).unwrap() {
+        let schema:String = row.get(1);
+        let name:String = row.get(2);
+        let definition:String = row.get(3);
+        if objects.iter().any(|object| object.schema == schema && object.name == name) {
+            continue;
+        }
+
+        let qualified_name = sql::qualified_table(&schema, &name);
+        objects.push(DependentObject { kind: ObjectKind::View, description: format!("view {}.{}", schema, name),
+            statements: vec!(format!("CREATE VIEW {} AS {}", qualified_name, definition.trim().trim_end_matches(';'))), schema, name });
+        add_views_over(source_client, &qualified_name, objects);
+    }
+}
+
+// Create the objects that don't exist in the target DB yet, each one in its own transaction
+fn create_objects(objects:&[DependentObject]) {
+    if objects.is_empty() {
+        return;
+    }
+
+    let mut target_client = match Client::connect(config::get_target_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    for object in objects {
+        if exists_in_target(&mut target_client, object) {
+            continue;
+        }
+        if object.statements.is_empty() {
+            println!("WARNING: The {} doesn't exist in target DB and can't be created by pgimporter. Create it before importing", object.description);
+            continue;
+        }
+        if config::is_dry_run() {
+            println!("DRY RUN: Would create {}", object.description);
+            continue;
+        }
+
+        let mut statements = vec!(format!("CREATE SCHEMA IF NOT EXISTS {}", sql::quote_ident(&object.schema)));
+        statements.extend(object.statements.iter().cloned());
+        let mut transaction = target_client.transaction().unwrap();
+        let failed_statement = statements.iter()
+            .find_map(|statement| transaction.batch_execute(statement).err().map(|error| (statement, error)));
+        match failed_statement {
+            None => {
+                transaction.commit().unwrap();
+                println!("INFO: Created {} in target DB", object.description);
+            },
+            // A view may also read tables that aren't imported, which doesn't stop the import
+            Some((_, error)) if matches!(object.kind, ObjectKind::View) => {
+                println!("WARNING: Couldn't create {} in target DB. Error: {}", object.description, error);
+            },
+            Some((statement, error)) => {
+                println!("ERROR: Couldn't create {} in target DB: {} | Error: {}", object.description, statement, error);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn exists_in_target(target_client:&mut Client, object:&DependentObject) -> bool {
+    let rows = match object.kind {
+        ObjectKind::Extension => target_client.query("SELECT 1 FROM pg_extension WHERE extname = $1", &[&object.name]),
+        ObjectKind::Type => target_client.query("SELECT 1 FROM pg_type pgt JOIN pg_namespace pgn ON pgn.oid = pgt.typnamespace
+            WHERE pgn.nspname = $1 AND pgt.typname = $2", &[&object.schema, &object.name]),
+        ObjectKind::Sequence | ObjectKind::View => target_client.query("SELECT 1 FROM pg_class pgc JOIN pg_namespace pgn ON pgn.oid = pgc.relnamespace
+            WHERE pgn.nspname = $1 AND pgc.relname = $2", &[&object.schema, &object.name])
+    };
+
+    !rows.unwrap().is_empty()
+}
@@ -0,0 +1,338 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use postgres::{Client, NoTls};
+use regex::Regex;
+
+use crate::config;
+use crate::config::ImportOptions;
+use crate::db;
+use crate::db::TableColumn;
+use crate::sql;
+
+// Keys of each keys file or subset table (its name and its keys), loaded into the temporary table pgimporter_keys_N of every connection
+// whose queries filter rows by them, N being the position of the file in the list
+lazy_static! {
+    static ref KEYS_TABLES: RwLock<Vec<(String, Vec<String>)>> = RwLock::new(vec!());
+}
+
+// Import only the rows with the given value (column=value) in every table that has the column, e.g. the ones of a tenant.
+// Tables without the column are imported in full or skipped, as chosen with tables_without_filter (IMPORT or SKIP)
+pub fn filter_by_column(tables:Vec<(String, String, ImportOptions)>, filter_column:&str, tables_without_filter:&str)
+    -> Vec<(String, String, ImportOptions)> {
+    if filter_column.is_empty() {
+        return tables;
+    }
+
+    let (column, value) = match filter_column.split_once('=') {
+        Some((column, value)) => (column.trim(), value.trim()),
+        None => {
+            println!("ERROR: Invalid filter column: {}. It must be column=value, e.g. tenant_id=42", filter_column);
+            std::process::exit(1);
+        }
+    };
+
+    let condition = format!("{} = {}", sql::quote_ident(column), sql::quote_literal(value));
+    let filtered_tables = filter_tables_with_column(tables, column, tables_without_filter, |_| condition.to_owned());
+    println!("INFO: Importing only the rows with {} = {}", column, value);
+
+    filtered_tables
+}
+
+// Import only the rows whose key column has one of the keys in the file. The keys are loaded into a temporary table
+// of each connection reading the rows, so the WHERE clause only joins them, however many keys there are
+pub fn filter_by_keys_file(tables:Vec<(String, String, ImportOptions)>, keys_file:&str, key_column:&str, tables_without_filter:&str)
+    -> Vec<(String, String, ImportOptions)> {
+    if keys_file.is_empty() {
+        return tables;
+    }
+    if key_column.is_empty() {
+        println!("ERROR: A keys file needs the key column to find the keys in");
+        std::process::exit(1);
+    }
+
+    let keys = read_keys_file(keys_file, key_column);
+    if keys.is_empty() {
+        println!("ERROR: Keys file {} doesn't have any key", keys_file);
+        std::process::exit(1);
+    }
+
+    let keys_count = keys.len();
+    let keys_table = keys_table_for(keys_file, keys);
+
+    // Cast to the type of the column, so its indexes can be used
+    let filtered_tables = filter_tables_with_column(tables, key_column, tables_without_filter,
+        |column| format!("{} IN (SELECT key::{} FROM pg_temp.{})", sql::quote_ident(key_column), column.data_type, keys_table));
+    println!("INFO: Importing only the rows with {} in the {} keys of {}", key_column, keys_count, keys_file);
+
+    filtered_tables
+}
+
+// Import only the rows of a time window in the time column of each table: since a period ago (e.g. 7d) or a date, or between two dates
+// (the first one included and the last one excluded). The start and end of the window are computed once in the source DB,
+// so all tables and all the chunks of a multi-thread import get the same rows, however long the import takes
+pub fn filter_by_time_window(tables:Vec<(String, String, ImportOptions)>, since:&str, between:&str, tables_without_filter:&str)
+    -> Vec<(String, String, ImportOptions)> {
+    if since.is_empty() && between.is_empty() {
+        return tables;
+    }
+    if !since.is_empty() && !between.is_empty() {
+        println!("ERROR: A time window can be given with since or with between, but not both");
+        std::process::exit(1);
+    }
+
+    let mut source_client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+    let (start, end) = match between.split_once("..") {
+        Some((start, end)) => (point_in_time(&mut source_client, start), Some(point_in_time(&mut source_client, end))),
+        None if between.is_empty() => (point_in_time(&mut source_client, since), None),
+        None => {
+            println!("ERROR: Invalid time window: {}. It must be start..end, e.g. 2026-09-01..2026-10-01", between);
+            std::process::exit(1);
+        }
+    };
+
+    let skip_tables_without_filter = skip_tables_without_filter(tables_without_filter);
+    let mut filtered_tables = vec!();
+    for (schema, table, mut options) in tables {
+        let has_time_column = !options.time_column.is_empty()
+            && db::get_source_columns(&schema, &table, &options).iter().any(|source_column| source_column.name == options.time_column);
+        if !has_time_column {
+            let missing_column = match options.time_column.is_empty() {
+                true => String::from("a time column"),
+                false => format!("time column {}", options.time_column)
+            };
+            if skip_tables_without_filter {
+                println!("WARNING: Table {}.{} doesn't have {}. Skipping it", schema, table, missing_column);
+            }
+            else {
+                println!("WARNING: Table {}.{} doesn't have {}. Importing all its rows", schema, table, missing_column);
+                filtered_tables.push((schema, table, options));
+            }
+            continue;
+        }
+
+        let time_column = sql::quote_ident(&options.time_column);
+        let mut condition = format!("{} >= {}::timestamptz", time_column, sql::quote_literal(&start));
+        if let Some(end) = &end {
+            condition = format!("{} AND {} < {}::timestamptz", condition, time_column, sql::quote_literal(end));
+        }
+        options.where_clause = sql::and_where(&options.where_clause, &condition);
+        filtered_tables.push((schema, table, options));
+    }
+
+    match &end {
+        Some(end) => println!("INFO: Importing only the rows from {} to {} in the time column of each table", start, end),
+        None => println!("INFO: Importing only the rows since {} in the time column of each table", start)
+    }
+
+    filtered_tables
+}
+
+// A period ago (a number of minutes, hours, days or weeks, e.g. 30m, 12h, 7d or 2w), or a date or timestamp
+fn point_in_time(source_client:&mut Client, point:&str) -> String {
+    let point = point.trim();
+    let point_query = match interval_of(point) {
+        Some(interval) => format!("SELECT (now() - interval {})::text", sql::quote_literal(&interval)),
+        None => format!("SELECT {}::timestamptz::text", sql::quote_literal(point))
+    };
+
+    match source_client.query_one(point_query.as_str(), &[]) {
+        Ok(row) => row.get(0),
+        Err(error) => {
+            println!("ERROR: Invalid time: {}. It must be a period (e.g. 7d, 12h, 30m or 2w) or a date (e.g. 2026-09-01) | Error: {}", point, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Interval of a period (e.g. 7d is 7 days), or None if it isn't a period
+fn interval_of(period:&str) -> Option<String> {
+    let period_regex = Regex::new(r"^(\d+)\s*(m|h|d|w)$").unwrap();
+    let captures = period_regex.captures(period)?;
+    let unit = match &captures[2] {
+        "m" => "minutes",
+        "h" => "hours",
+        "d" => "days",
+        _ => "weeks"
+    };
+
+    Some(format!("{} {}", &captures[1], unit))
+}
+
+fn skip_tables_without_filter(tables_without_filter:&str) -> bool {
+    match tables_without_filter.to_uppercase().as_str() {
+        "IMPORT" => false,
+        "SKIP" => true,
+        _ => {
+            println!("ERROR: Invalid value for tables without filter column: {}. Valid values are IMPORT or SKIP", tables_without_filter);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Add the condition for the column to the WHERE clause of the tables that have it
+fn filter_tables_with_column<F>(tables:Vec<(String, String, ImportOptions)>, column:&str, tables_without_filter:&str, condition_for:F)
+    -> Vec<(String, String, ImportOptions)> where F: Fn(&TableColumn) -> String {
+    let skip_tables_without_filter = skip_tables_without_filter(tables_without_filter);
+
+    let mut filtered_tables = vec!();
+    for (schema, table, mut options) in tables {
+        let source_columns = db::get_source_columns(&schema, &table, &options);
+        if let Some(source_column) = source_columns.iter().find(|source_column| source_column.name == column) {
+            options.where_clause = sql::and_where(&options.where_clause, &condition_for(source_column));
+            filtered_tables.push((schema, table, options));
+        }
+        else if skip_tables_without_filter {
+            println!("WARNING: Table {}.{} doesn't have column {}. Skipping it", schema, table, column);
+        }
+        else {
+            println!("WARNING: Table {}.{} doesn't have column {}. Importing all its rows", schema, table, column);
+            filtered_tables.push((schema, table, options));
+        }
+    }
+
+    filtered_tables
+}
+
+// Temporary table with the given keys (of a keys file, or chosen some other way), named after the position of its keys.
+// The same keys given again with the same name are already in a table
+pub fn keys_table_for(name:&str, keys:Vec<String>) -> String {
+    let mut keys_tables = KEYS_TABLES.write().unwrap();
+    match keys_tables.iter().position(|(keys_name, table_keys)| keys_name == name && *table_keys == keys) {
+        Some(position) => format!("pgimporter_keys_{}", position),
+        None => {
+            keys_tables.push((name.to_string(), keys));
+            format!("pgimporter_keys_{}", keys_tables.len() - 1)
+        }
+    }
+}
+
+// Load the keys of the keys files into temporary tables of the connection, so its queries can filter rows by them.
+// Run again in the same connection, the tables are created again
+pub fn use_keys_tables(client:&mut Client) {
+    let keys_tables = KEYS_TABLES.read().unwrap();
+    for (position, (keys_name, keys)) in keys_tables.iter().enumerate() {
+        let keys_table = format!("pg_temp.pgimporter_keys_{}", position);
+        let mut rows = vec!();
+        for key in keys {
+            rows.extend_from_slice(sql::escape_copy_text(key).as_bytes());
+            rows.push(b'\n');
+        }
+
+        let create_table = format!("DROP TABLE IF EXISTS {}; CREATE TEMP TABLE {} (key text)", keys_table, keys_table);
+        if let Err(error) = client.batch_execute(&create_table) {
+            println!("ERROR: Couldn't create temporary table {} for the keys of {} | Error: {}", keys_table, keys_name, error);
+            std::process::exit(1);
+        }
+        let mut writer = match client.copy_in(format!("COPY {} FROM STDIN", keys_table).as_str()) {
+            Ok(writer) => writer,
+            Err(error) => { println!("ERROR: Couldn't load the keys of {} | Error: {}", keys_name, error); std::process::exit(1); }
+        };
+        if let Err(error) = writer.write_all(&rows) {
+            println!("ERROR: Couldn't load the keys of {} | Error: {}", keys_name, error);
+            std::process::exit(1);
+        }
+        if let Err(error) = writer.finish() {
+            println!("ERROR: Couldn't load the keys of {} | Error: {}", keys_name, error);
+            std::process::exit(1);
+        }
+        client.batch_execute(&format!("ANALYZE {}", keys_table)).unwrap();
+    }
+}
+
+// One key per line, in the first field of a CSV line (quoted if it has commas or quotes, doubling the quotes inside it).
+// A first line with the name of the key column is a header
+fn read_keys_file(keys_file:&str, key_column:&str) -> Vec<String> {
+    let file = match File::open(keys_file) {
+        Ok(file) => file,
+        Err(error) => { println!("ERROR: Couldn't open keys file {} : {}", keys_file, error); std::process::exit(1); }
+    };
+
+    let mut keys:Vec<String> = vec!();
+    let mut read_keys = HashSet::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => { println!("ERROR: Couldn't read keys file {} : {}", keys_file, error); std::process::exit(1); }
+        };
+        let key = first_csv_field(&line);
+        if key.is_empty() || (i == 0 && key == key_column) || !read_keys.insert(key.to_owned()) {
+            continue;
+        }
+        keys.push(key);
+    }
+
+    keys
+}
+
+// First field of a CSV line, without its quotes. Keys can't have new lines, as each line is a key
+fn first_csv_field(line:&str) -> String {
+    let line = line.trim();
+    if !line.starts_with('"') {
+        return line.split(',').next().unwrap_or_default().trim().to_string();
+    }
+
+    let mut field = String::new();
+    let mut chars = line[1..].chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            // A doubled quote is a quote inside the field, and a single one ends it
+            if chars.peek() != Some(&'"') {
+                break;
+            }
+            chars.next();
+        }
+        field.push(c);
+    }
+
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_of_periods() {
+        assert_eq!(interval_of("30m"), Some(String::from("30 minutes")));
+        assert_eq!(interval_of("12h"), Some(String::from("12 hours")));
+        assert_eq!(interval_of("7 d"), Some(String::from("7 days")));
+        assert_eq!(interval_of("2w"), Some(String::from("2 weeks")));
+    }
+
+    #[test]
+    fn interval_of_dates_is_none() {
+        assert_eq!(interval_of("2026-09-01"), None);
+        assert_eq!(interval_of("7y"), None);
+        assert_eq!(interval_of("d"), None);
+    }
+
+    #[test]
+    fn first_csv_field_of_plain_lines() {
+        assert_eq!(first_csv_field("42"), "42");
+        assert_eq!(first_csv_field(" 42 ,shipped,2026-10-01"), "42");
+        assert_eq!(first_csv_field(""), "");
+    }
+
+    #[test]
+    fn first_csv_field_of_quoted_lines() {
+        assert_eq!(first_csv_field("\"ACME, Inc.\",3"), "ACME, Inc.");
+        assert_eq!(first_csv_field("\"the \"\"best\"\" one\""), "the \"best\" one");
+        assert_eq!(first_csv_field("\"\",3"), "");
+    }
+
+    #[test]
+    fn read_keys_file_skips_header_and_repeated_keys() {
+        let keys_file = std::env::temp_dir().join("pgimporter_read_keys_file.csv");
+        std::fs::write(&keys_file, "code,comment\nes,Spain\n\"x,\"\"y\",other\n\nes,again\n").unwrap();
+
+        let keys = read_keys_file(keys_file.to_str().unwrap(), "code");
+        assert_eq!(keys, vec!(String::from("es"), String::from("x,\"y")));
+    }
+}
@@ -0,0 +1,328 @@
+use postgres::{Client, NoTls};
+
+use crate::config;
+use crate::config::ImportOptions;
+use crate::db;
+use crate::sql;
+use crate::versions;
+
+// Foreign key between two of the tables to import, read from the target DB, where it's checked
+struct ForeignKey {
+    name:String,
+    // Positions of the referencing (child) and referenced (parent) tables in the tables to import
+    child:usize,
+    parent:usize,
+    // Referencing columns of the child table and the columns of the parent table they reference, in the same order
+    columns:Vec<String>,
+    referenced_columns:Vec<String>,
+    deferrable:bool,
+    definition:String
+}
+
+// Import the tables so foreign keys between them don't fail: truncating them children first (in a single TRUNCATE)
+// and loading them parents first. A foreign key referencing its own table is satisfied loading the referenced rows first.
+// Other foreign keys in a cycle can't be satisfied by any order, so the tables of the cycle are loaded in a single transaction
+pub fn import_tables(tables:Vec<(String, String, ImportOptions)>) {
+    let foreign_keys = get_foreign_keys_between(&tables);
+    let parent_first_rows:Vec<Option<(String, String)>> = (0..tables.len())
+        .map(|table_index| parent_first_rows_of(&tables, &foreign_keys, table_index))
+        .collect();
+    let deferred_keys:Vec<&ForeignKey> = foreign_keys.iter()
+        .filter(|foreign_key| depends_on(&foreign_keys, foreign_key.parent, foreign_key.child))
+        .filter(|foreign_key| foreign_key.child != foreign_key.parent || parent_first_rows[foreign_key.child].is_none())
+        .collect();
+
+    let load_order = load_order_of(tables.len(), &foreign_keys);
+    if load_order.iter().enumerate().any(|(position, table_index)| position != *table_index) {
+        let table_names:Vec<String> = load_order.iter().map(|i| format!("{}.{}", tables[*i].0, tables[*i].1)).collect();
+        println!("INFO: Importing tables in foreign key order: {}", table_names.join(", "));
+    }
+
+    // Tables of a cycle are truncated in its transaction, so a failure leaves them as they were
+    let in_cycle:Vec<bool> = (0..tables.len())
+        .map(|table_index| deferred_keys.iter().any(|foreign_key| cycle_of(tables.len(), &foreign_keys, table_index).contains(&foreign_key.child)))
+        .collect();
+    check_partitions_of(&tables, &in_cycle, &parent_first_rows);
+
+    let truncate_order:Vec<&(String, String, ImportOptions)> = load_order.iter().rev()
+        .filter(|i| !in_cycle[**i])
+        .map(|i| &tables[*i])
+        .collect();
+    db::truncate_target_tables(&truncate_order, None);
+
+    // The tables of a cycle are always next to each other in the load order
+    let mut position = 0;
+    while position < load_order.len() {
+        let cycle = cycle_of(tables.len(), &foreign_keys, load_order[position]);
+        let cycle_keys:Vec<&ForeignKey> = deferred_keys.iter().filter(|foreign_key| cycle.contains(&foreign_key.child)).copied().collect();
+        if cycle_keys.is_empty() {
+            let (schema, table, options) = &tables[load_order[position]];
+            db::import_table_from(schema.to_owned(), table.to_owned(), options, parent_first_rows[load_order[position]].as_ref(), None);
+        }
+        else {
+            // Tables referencing the cycle must be truncated along with it, even if they're already empty
+            let truncated_tables:Vec<&(String, String, ImportOptions)> = load_order.iter().rev()
+                .filter(|i| cycle.contains(i) || cycle.iter().any(|table_index| depends_on(&foreign_keys, **i, *table_index)))
+                .map(|i| &tables[*i])
+                .collect();
+            import_cycle(&tables, &cycle, &cycle_keys, &parent_first_rows, &truncated_tables);
+        }
+        position += cycle.len();
+    }
+}
+
+// Truncate and load the tables of a cycle of foreign keys in a single transaction of the target DB, so a failure leaves them as they were.
+// DEFERRABLE keys are checked at the end of the transaction, and the other ones are dropped and created again in it
+fn import_cycle(tables:&[(String, String, ImportOptions)], cycle:&[usize], cycle_keys:&[&ForeignKey], parent_first_rows:&[Option<(String, String)>],
+    truncated_tables:&[&(String, String, ImportOptions)]) {
+    let mut target_client = match Client::connect(config::get_target_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
+    };
+    let table_names:Vec<String> = cycle.iter().map(|i| format!("{}.{}", tables[*i].0, tables[*i].1)).collect();
+    println!("INFO: Importing tables {} in a single transaction, as their foreign keys are in a cycle", table_names.join(", "));
+    target_client.batch_execute("BEGIN").unwrap();
+
+    for foreign_key in cycle_keys {
+        let (schema, table, _) = &tables[foreign_key.child];
+        if foreign_key.deferrable {
+            let defer_key = format!("SET CONSTRAINTS {} DEFERRED", sql::qualified_table(schema, &foreign_key.name));
+            target_client.batch_execute(&defer_key).unwrap();
+            continue;
+        }
+        println!("INFO: Dropping foreign key {} of {}.{} ({}) until all tables of its cycle are imported", foreign_key.name, schema, table,
+            foreign_key.definition);
+        let drop_key = format!("ALTER TABLE {} DROP CONSTRAINT {}", sql::qualified_table(schema, table), sql::quote_ident(&foreign_key.name));
+        if let Err(error) = target_client.batch_execute(&drop_key) {
+            println!("ERROR: Couldn't drop foreign key {} of {}.{} | Error: {}", foreign_key.name, schema, table, error);
+            std::process::exit(1);
+        }
+    }
+
+    db::truncate_target_tables(truncated_tables, Some(&mut target_client));
+
+    for table_index in cycle {
+        let (schema, table, options) = &tables[*table_index];
+        db::import_table_from(schema.to_owned(), table.to_owned(), options, parent_first_rows[*table_index].as_ref(), Some(&mut target_client));
+    }
+
+    // The rows are checked when the keys are created again, or when the deferred ones are checked.
+    // Exiting closes the connection, which rolls back the transaction
+    for foreign_key in cycle_keys {
+        let (schema, table, _) = &tables[foreign_key.child];
+        let check_key = match foreign_key.deferrable {
+            true => format!("SET CONSTRAINTS {} IMMEDIATE", sql::qualified_table(schema, &foreign_key.name)),
+            false => format!("ALTER TABLE {} ADD CONSTRAINT {} {}", sql::qualified_table(schema, table), sql::quote_ident(&foreign_key.name),
+                foreign_key.definition)
+        };
+        if let Err(error) = target_client.batch_execute(&check_key) {
+            println!("ERROR: Imported rows don't satisfy foreign key {} of {}.{}. Rolling back the import of tables {} | Error: {}",
+                foreign_key.name, schema, table, table_names.join(", "), error);
+            std::process::exit(1);
+        }
+        if !foreign_key.deferrable {
+            println!("INFO: Created again foreign key {} of {}.{}", foreign_key.name, schema, table);
+        }
+    }
+
+    if let Err(error) = target_client.batch_execute("COMMIT") {
+        println!("ERROR: Couldn't commit the import of tables {} | Error: {}", table_names.join(", "), error);
+        std::process::exit(1);
+    }
+}
+
+// Tables loaded in a transaction or in the order of their rows are read as a whole table, so their partitions can't be chosen
+fn check_partitions_of(tables:&[(String, String, ImportOptions)], in_cycle:&[bool], parent_first_rows:&[Option<(String, String)>]) {
+    for (table_index, (schema, table, options)) in tables.iter().enumerate() {
+        if options.partitions.is_empty() {
+            continue;
+        }
+        if in_cycle[table_index] {
+            println!("ERROR: Can't select partitions of {}.{}, as its foreign keys are in a cycle and it's imported in a single transaction. Import the whole table instead",
+                schema, table);
+            std::process::exit(1);
+        }
+        if parent_first_rows[table_index].is_some() {
+            println!("ERROR: Can't select partitions of {}.{}, as its rows are loaded in the order of its foreign key to itself. Import the whole table instead",
+                schema, table);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn get_foreign_keys_between(tables:&[(String, String, ImportOptions)]) -> Vec<ForeignKey> {
+    let mut target_client = match Client::connect(config::get_target_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    let foreign_keys_query = "SELECT pgco.conname::text, child_ns.nspname::text, child.relname::text, parent_ns.nspname::text, parent.relname::text,
+            pg_get_constraintdef(pgco.oid), pgco.condeferrable,
+            ARRAY(SELECT pga.attname::text FROM unnest(pgco.conkey) WITH ORDINALITY AS key (attnum, position)
+                JOIN pg_attribute pga ON pga.attrelid = pgco.conrelid AND pga.attnum = key.attnum ORDER BY key.position),
+            ARRAY(SELECT pga.attname::text FROM unnest(pgco.confkey) WITH ORDINALITY AS key (attnum, position)
+                JOIN pg_attribute pga ON pga.attrelid = pgco.confrelid AND pga.attnum = key.attnum ORDER BY key.position)
+        FROM pg_constraint pgco
+        JOIN pg_class child ON child.oid = pgco.conrelid
+        JOIN pg_namespace child_ns ON child_ns.oid = child.relnamespace
+        JOIN pg_class parent ON parent.oid = pgco.confrelid
+        JOIN pg_namespace parent_ns ON parent_ns.oid = parent.relnamespace
+        WHERE pgco.contype = 'f' AND child_ns.nspname = ANY($1)
+        ORDER BY pgco.conname";
+
+    let schemas:Vec<String> = tables.iter().map(|(schema, _, _)| schema.to_owned()).collect();
+    let position_of = |schema:&String, table:&String| tables.iter()
+        .position(|(table_schema, table_name, _)| table_schema == schema && table_name == table);
+
+    let mut foreign_keys = vec!();
+    for row in target_client.query(foreign_keys_query, &[&schemas]).unwrap() {
+        let (child_schema, child_table, parent_schema, parent_table):(String, String, String, String) = (row.get(1), row.get(2), row.get(3), row.get(4));
+        // Keys referencing tables that aren't imported don't change the order
+        if let (Some(child), Some(parent)) = (position_of(&child_schema, &child_table), position_of(&parent_schema, &parent_table)) {
+            foreign_keys.push(ForeignKey { name: row.get(0), child, parent, columns: row.get(7), referenced_columns: row.get(8),
+                deferrable: row.get(6), definition: row.get(5) });
+        }
+    }
+
+    foreign_keys
+}
+
+// Relation with the rows of the table and their level in the hierarchy of the foreign key referencing the table itself, and the
+// ORDER BY loading first the rows referenced by other ones, when the table has a single foreign key like that. None if it doesn't,
+// or if the rows can't be read by the columns of the key (rows of a query, renamed or computed columns, rows chosen by their order,
+// or a sample, as TABLESAMPLE only applies to tables)
+fn parent_first_rows_of(tables:&[(String, String, ImportOptions)], foreign_keys:&[ForeignKey], table_index:usize) -> Option<(String, String)> {
+    let (schema, table, options) = &tables[table_index];
+    let self_keys:Vec<&ForeignKey> = foreign_keys.iter()
+        .filter(|foreign_key| foreign_key.child == table_index && foreign_key.parent == table_index)
+        .collect();
+    if self_keys.len() != 1 || !options.query.is_empty() || !options.order_by.is_empty() || options.sample_percent.is_some() {
+        return None;
+    }
+
+    let foreign_key = self_keys[0];
+    let source_columns = db::get_source_columns(schema, table, options);
+    let key_columns:Vec<&String> = foreign_key.columns.iter().chain(foreign_key.referenced_columns.iter()).collect();
+    if key_columns.iter().any(|column| !source_columns.iter().any(|source_column| &&source_column.name == column)
+        || options.renamed_columns.contains_key(*column) || options.renamed_columns.values().any(|target_column| &target_column == column)
+        || options.column_expressions.contains_key(*column)) {
+        return None;
+    }
+
+    let source_table = db::table_without_children(config::get_source_db_url().as_str(), *versions::SOURCE_VERSION, schema, table);
+    Some((hierarchy_levels_of(&source_table, &foreign_key.columns, &foreign_key.referenced_columns), String::from("pgimporter_level NULLS LAST")))
+}
+
+// The rows of the table with their level: 0 for the rows not referencing any row, 1 for the ones referencing them, and so on.
+// Rows in a cycle of references are never reached, so they don't have any level. Levels are bounded by the number of rows,
+// in case the referenced columns aren't unique in the source table
+fn hierarchy_levels_of(source_table:&str, columns:&[String], referenced_columns:&[String]) -> String {
+    let level_keys:Vec<String> = (1..=columns.len()).map(|i| format!("pgimporter_key_{}", i)).collect();
+    let row_keys:Vec<String> = referenced_columns.iter().map(|column| format!("pgimporter_row.{}", sql::quote_ident(column))).collect();
+    let join_condition = |row_columns:&[String]| -> String {
+        row_columns.iter().zip(&level_keys)
+            .map(|(column, level_key)| format!("pgimporter_row.{} = pgimporter_levels.{}", sql::quote_ident(column), level_key))
+            .collect::<Vec<String>>().join(" AND ")
+    };
+    let parent_condition:Vec<String> = columns.iter().zip(referenced_columns)
+        .map(|(column, referenced_column)| format!("pgimporter_parent.{} = pgimporter_row.{}", sql::quote_ident(referenced_column), sql::quote_ident(column)))
+        .collect();
+
+    format!("(WITH RECURSIVE pgimporter_levels ({}, pgimporter_level) AS (\
+            SELECT {}, 0 FROM {} AS pgimporter_row WHERE NOT EXISTS (SELECT FROM {} AS pgimporter_parent WHERE {}) \
+            UNION ALL SELECT {}, pgimporter_levels.pgimporter_level + 1 FROM {} AS pgimporter_row JOIN pgimporter_levels ON {} \
+                WHERE pgimporter_levels.pgimporter_level < (SELECT count(*) FROM {})) \
+        SELECT pgimporter_row.*, pgimporter_levels.pgimporter_level FROM {} AS pgimporter_row LEFT JOIN pgimporter_levels ON {}) AS pgimporter_rows",
+        level_keys.join(", "), row_keys.join(", "), source_table, source_table, parent_condition.join(" AND "), row_keys.join(", "), source_table,
+        join_condition(columns), source_table, source_table, join_condition(referenced_columns))
+}
+
+// Whether the table references the other one, directly or through other tables. A key whose parent depends on its child is in a cycle
+fn depends_on(foreign_keys:&[ForeignKey], table:usize, other_table:usize) -> bool {
+    let mut visited = vec!(table);
+    let mut pending = vec!(table);
+    while let Some(current) = pending.pop() {
+        for foreign_key in foreign_keys.iter().filter(|foreign_key| foreign_key.child == current) {
+            if foreign_key.parent == other_table {
+                return true;
+            }
+            if !visited.contains(&foreign_key.parent) {
+                visited.push(foreign_key.parent);
+                pending.push(foreign_key.parent);
+            }
+        }
+    }
+
+    false
+}
+
+// The table and the tables in a cycle of foreign keys with it, in the order they were chosen
+fn cycle_of(table_count:usize, foreign_keys:&[ForeignKey], table:usize) -> Vec<usize> {
+    (0..table_count)
+        .filter(|other_table| *other_table == table || (depends_on(foreign_keys, table, *other_table) && depends_on(foreign_keys, *other_table, table)))
+        .collect()
+}
+
+// Parents before their children, keeping the order the tables were chosen in otherwise. The tables of a cycle are loaded one
+// after the other, once the tables outside the cycle they reference are loaded. Without the keys in cycles there are no cycles left,
+// so there's always a table whose parents are already loaded
+fn load_order_of(table_count:usize, foreign_keys:&[ForeignKey]) -> Vec<usize> {
+    let mut load_order:Vec<usize> = vec!();
+    while load_order.len() < table_count {
+        let next_table = (0..table_count)
+            .find(|table| !load_order.contains(table) && foreign_keys.iter()
+                .filter(|foreign_key| cycle_of(table_count, foreign_keys, *table).contains(&foreign_key.child))
+                .filter(|foreign_key| !depends_on(foreign_keys, foreign_key.parent, foreign_key.child))
+                .all(|foreign_key| load_order.contains(&foreign_key.parent)))
+            .unwrap();
+        load_order.extend(cycle_of(table_count, foreign_keys, next_table));
+    }
+
+    load_order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn foreign_key(child:usize, parent:usize) -> ForeignKey {
+        ForeignKey { name: format!("fk_{}_{}", child, parent), child, parent, columns: vec!(), referenced_columns: vec!(),
+            deferrable: false, definition: String::new() }
+    }
+
+    #[test]
+    fn depends_on_follows_keys_through_other_tables() {
+        let foreign_keys = vec!(foreign_key(0, 1), foreign_key(1, 2));
+        assert!(depends_on(&foreign_keys, 0, 1));
+        assert!(depends_on(&foreign_keys, 0, 2));
+        assert!(!depends_on(&foreign_keys, 2, 0));
+        assert!(!depends_on(&foreign_keys, 0, 0));
+    }
+
+    #[test]
+    fn load_order_puts_parents_first() {
+        // 0 references 2, and 1 references 0
+        let foreign_keys = vec!(foreign_key(0, 2), foreign_key(1, 0));
+        assert_eq!(load_order_of(3, &foreign_keys), vec!(2, 0, 1));
+    }
+
+    #[test]
+    fn load_order_keeps_cycles_together() {
+        // 1 and 3 are in a cycle, 3 references 0, 2 references 1, and 0 references itself
+        let foreign_keys = vec!(foreign_key(1, 3), foreign_key(3, 1), foreign_key(3, 0), foreign_key(2, 1), foreign_key(0, 0));
+        assert_eq!(cycle_of(4, &foreign_keys, 3), vec!(1, 3));
+        assert_eq!(cycle_of(4, &foreign_keys, 0), vec!(0));
+        assert_eq!(load_order_of(4, &foreign_keys), vec!(0, 1, 3, 2));
+    }
+
+    #[test]
+    fn hierarchy_levels_join_every_key_column() {
+        let levels = hierarchy_levels_of("ONLY \"s\".\"t\"", &[String::from("parent_a"), String::from("parent_b")],
+            &[String::from("a"), String::from("b")]);
+        assert!(levels.contains("WHERE pgimporter_parent.\"a\" = pgimporter_row.\"parent_a\" AND pgimporter_parent.\"b\" = pgimporter_row.\"parent_b\""));
+        assert!(levels.contains("JOIN pgimporter_levels ON pgimporter_row.\"parent_a\" = pgimporter_levels.pgimporter_key_1 \
+            AND pgimporter_row.\"parent_b\" = pgimporter_levels.pgimporter_key_2"));
+        assert!(levels.ends_with("LEFT JOIN pgimporter_levels ON pgimporter_row.\"a\" = pgimporter_levels.pgimporter_key_1 \
+            AND pgimporter_row.\"b\" = pgimporter_levels.pgimporter_key_2) AS pgimporter_rows"));
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::{HashMap, HashSet};
+use postgres::{Client, Error, NoTls};
+
+use crate::config::ImportConfig;
+use crate::db;
+
+// Modes for lo_open
+const INV_WRITE:i32 = 0x20000;
+const INV_READ:i32 = 0x40000;
+
+// Size of each piece of a large object read from source and written to target
+const LARGE_OBJECT_CHUNK_SIZE:i32 = 1024 * 1024;
+
+// Source connection reading the large objects referenced by the rows, as the one reading the rows is busy with them. None if the rows
+// don't reference any. It reads them in the snapshot of the rows if any, in a transaction kept open while importing, as large object
+// descriptors are only valid inside one
+pub fn large_objects_client_for(import_config:&ImportConfig, snapshot_id:&Option<String>) -> Option<Client> {
+    if import_config.large_object_columns.is_empty() {
+        return None;
+    }
+
+    let mut source_client = match Client::connect(import_config.source_db_url.as_ref(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+    match snapshot_id {
+        Some(_) => db::use_source_snapshot(&mut source_client, snapshot_id),
+        None => source_client.batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ").unwrap()
+    }
+
+    Some(source_client)
+}
+
+// Copy to the target DB the large objects referenced by the rows in the buffer (COPY text format), read with the large objects client,
+// returning the same rows with the oids of the new large objects
+pub fn copy_large_objects_in_buffer(import_config:&ImportConfig, source_client:&mut Client, target_client:&mut Client, buffer:&[u8])
+    -> Result<Vec<u8>, Error> {
+    // Several rows can reference the same large object
+    let mut target_oids:HashMap<String, String> = HashMap::new();
+    let mut new_buffer = vec!();
+
+    // Rows end with a new line and their values are separated by tabs (those inside values are escaped)
+    for row in buffer.split(|byte| *byte == b'\n').filter(|row| !row.is_empty()) {
+        let mut values:Vec<Vec<u8>> = row.split(|byte| *byte == b'\t').map(|value| value.to_vec()).collect();
+
+        for column_index in &import_config.large_object_columns {
+            let source_oid = String::from_utf8_lossy(&values[*column_index]).to_string();
+            // NULL
+            if source_oid == "\\N" {
+                continue;
+            }
+
+            if !target_oids.contains_key(&source_oid) {
+                let target_oid = match source_oid.parse() {
+                    Ok(oid) => copy_large_object(import_config, source_client, target_client, oid)?.to_string(),
+                    Err(_) => String::from("\\N")
+                };
+                target_oids.insert(source_oid.to_owned(), target_oid);
+            }
+            values[*column_index] = target_oids[&source_oid].as_bytes().to_vec();
+        }
+
+        new_buffer.extend(values.join(&b'\t'));
+        new_buffer.push(b'\n');
+    }
+
+    Ok(new_buffer)
+}
+
+// Remove from the target DB the large objects copied for the rejected rows, unless an accepted row of the same buffer
+// references them too (large objects are only shared by the rows of the same buffer)
+pub fn unlink_large_objects_of(import_config:&ImportConfig, target_client:&mut Client, rejected_rows:&[&[u8]], accepted_rows:&[&[u8]]) {
+    let accepted_oids = large_objects_of(import_config, accepted_rows);
+    for oid in large_objects_of(import_config, rejected_rows).difference(&accepted_oids) {
+        if let Err(error) = target_client.execute("SELECT lo_unlink($1)", &[oid]) {
+            println!("WARNING: Couldn't remove large object {} of a rejected row from target DB. Error: {}", oid, error);
+        }
+    }
+}
+
+// Oids of the large objects referenced by the rows (COPY text format)
+fn large_objects_of(import_config:&ImportConfig, rows:&[&[u8]]) -> HashSet<u32> {
+    let mut oids = HashSet::new();
+    for row in rows {
+        let values:Vec<&[u8]> = row.split(|byte| *byte == b'\t').collect();
+        for column_index in &import_config.large_object_columns {
+            if let Some(oid) = values.get(*column_index).and_then(|value| String::from_utf8_lossy(value).parse().ok()) {
+                oids.insert(oid);
+            }
+        }
+    }
+
+    oids
+}
+
+// Stream a large object from source to target in chunks, reading it with the large objects client. Returns the oid of the new
+// large object in the target DB. A large object that can't be read (e.g. it doesn't exist) is an error, as the row would lose it
+pub fn copy_large_object(import_config:&ImportConfig, source_client:&mut Client, target_client:&mut Client, source_oid:u32) -> Result<u32, Error> {
+    let source_fd:i32 = source_client.query_one("SELECT lo_open($1, $2)", &[&source_oid, &INV_READ])?.get(0);
+
+    // The target may already be inside the transaction of the import
+    if !import_config.in_transaction {
+        target_client.batch_execute("BEGIN")?;
+    }
+    let target_oid:u32 = target_client.query_one("SELECT lo_create(0)", &[])?.get(0);
+    let target_fd:i32 = target_client.query_one("SELECT lo_open($1, $2)", &[&target_oid, &INV_WRITE])?.get(0);
+
+    loop {
+        let chunk:Vec<u8> = source_client.query_one("SELECT loread($1, $2)", &[&source_fd, &LARGE_OBJECT_CHUNK_SIZE])?.get(0);
+        if chunk.is_empty() {
+            break;
+        }
+        target_client.execute("SELECT lowrite($1, $2)", &[&target_fd, &chunk])?;
+    }
+
+    target_client.execute("SELECT lo_close($1)", &[&target_fd])?;
+    if !import_config.in_transaction {
+        target_client.batch_execute("COMMIT")?;
+    }
+    source_client.execute("SELECT lo_close($1)", &[&source_fd])?;
+
+    Ok(target_oid)
+}
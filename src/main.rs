@@ -5,23 +5,67 @@ mod config;
 mod batch;
 mod query;
 mod copy;
+mod rejects;
+mod large_objects;
+mod sql;
+mod ddl;
+mod check;
+mod foreign_keys;
+mod subset;
+mod filter;
+mod masking;
+mod versions;
 mod single_import;
 mod multi_import;
+mod partition_import;
 
 use dialoguer::{theme::ColorfulTheme, MultiSelect, Select, Input, Confirm};
 use log::LevelFilter;
 use chrono::{Utc};
 use std::env;
-use config::{CONFIG_PROPERTIES};
+use config::{CONFIG_PROPERTIES, ImportOptions};
 
 struct TableInfo{
     name: String,
-    rows: u64
+    rows: u64,
+    size: String,
+    partitions: i64,
+    parent: Option<String>,
+    children: i64,
+    kind: String,
+    // Level in the inheritance hierarchy of the listed tables
+    depth: usize
+}
+
+impl TableInfo {
+    // Views, materialized views and foreign tables
+    fn is_table(&self) -> bool {
+        self.kind == "table" || self.kind == "partitioned table"
+    }
 }
 
 impl ToString for TableInfo {
     fn to_string(&self) -> String {
-        return format!("{} - {} rows", &self.name, &self.rows);
+        let mut name = self.name.to_owned();
+        if self.depth > 0 {
+            name = format!("{}└ {}", "  ".repeat(self.depth - 1), &self.name);
+        }
+
+        // Views and foreign tables don't keep any rows in the source DB, so their size is unknown
+        if self.kind == "view" || self.kind == "foreign table" {
+            return format!("{} - {}", name, &self.kind);
+        }
+        if self.kind == "materialized view" {
+            return format!("{} - {} rows - {} - {}", name, &self.rows, &self.size, &self.kind);
+        }
+
+        if self.partitions > 0 {
+            return format!("{} - {} rows - {} - {} partitions", name, &self.rows, &self.size, &self.partitions);
+        }
+        if self.children > 0 {
+            return format!("{} - {} rows - {} - {} child tables", name, &self.rows, &self.size, &self.children);
+        }
+        format!("{} - {} rows - {}", name, self.rows, self.size)
     }
 }
 
@@ -32,19 +76,151 @@ fn main() {
     println!("Importing to Target DB: {}", config::get_target_db_url_with_hiding(true));
     println!();
 
+    if CONFIG_PROPERTIES.command != "import" && !config::is_check_command() {
+        println!("ERROR: Unknown command {}. Valid commands are import or check", CONFIG_PROPERTIES.command);
+        std::process::exit(1);
+    }
+
     if CONFIG_PROPERTIES.error_log {
         let error_log_filename = format!("pgimport_errors_{}.log", Utc::now().to_rfc3339());
         simple_logging::log_to_file(error_log_filename, LevelFilter::Error).unwrap();        
     }
 
-    if CONFIG_PROPERTIES.batch_filename.is_empty() {
-        execute_interactive();
-    }
-    else {
+    if !CONFIG_PROPERTIES.batch_filename.is_empty() {
         batch::execute_batch_file(&CONFIG_PROPERTIES.batch_filename);
         std::process::exit(0);
-    }        
+    }
+    else if !CONFIG_PROPERTIES.query.is_empty() {
+        execute_query_import(&CONFIG_PROPERTIES.query);
+    }
+    else {
+        execute_interactive();
+    }
+}
+
+// Import the result of a query into a target table, given by --target-table or asked to the user
+fn execute_query_import(query:&str) {
+    // Check if DB connection URLs are correct
+    if !utils::check_postgres_source_target_servers() {
+        std::process::exit(1);
+    }
+
+    let mut target_table = CONFIG_PROPERTIES.target_table.to_owned();
+    if target_table.is_empty() {
+        target_table = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Target table to import the query into: [schema.table]")
+            .interact()
+            .unwrap();
+    }
+
+    // Tables without schema are in public, as in Postgres by default
+    let (schema, table) = match target_table.split_once('.') {
+        Some((schema, table)) => (schema.to_string(), table.to_string()),
+        None => (String::from("public"), target_table.to_owned())
+    };
+
+    let mut chunk_key = vec!();
+    if CONFIG_PROPERTIES.max_threads > 1 {
+        chunk_key = ask_chunk_key("Columns of the query with unique values to import it in several threads: [Optional, comma separated]");
+    }
+
+    let (mut truncate, mut cascade) = (false, false);
+    if !config::is_check_command() {
+        (truncate, cascade) = ask_truncate("selected table");
+    }
+
+    let options = ImportOptions { truncate, cascade, chunk_key, query: query.to_string(), create_missing: CONFIG_PROPERTIES.create_missing,
+        add_missing_columns: CONFIG_PROPERTIES.add_missing_columns, time_column: CONFIG_PROPERTIES.time_column.to_owned(),
+        masking_rules: masking::rules_from_file(&CONFIG_PROPERTIES.masking_file), ..Default::default() };
+
+    let tables = filter::filter_by_column(vec!((schema, table, options)), &CONFIG_PROPERTIES.filter_column,
+        &CONFIG_PROPERTIES.tables_without_filter);
+    let tables = filter::filter_by_keys_file(tables, &CONFIG_PROPERTIES.keys_file, &CONFIG_PROPERTIES.key_column,
+        &CONFIG_PROPERTIES.tables_without_filter);
+    let tables = filter::filter_by_time_window(tables, &CONFIG_PROPERTIES.since, &CONFIG_PROPERTIES.between,
+        &CONFIG_PROPERTIES.tables_without_filter);
+    ddl::create_missing_objects(&tables);
+    let checked_tables = check::check_tables(tables);
+    for (schema, table, options) in &checked_tables {
+        db::check_where_clause(schema, table, options);
+        db::check_chunk_key(schema, table, options);
+    }
+    utils::exit_if_dry_run();
+
+    foreign_keys::import_tables(checked_tables);
+}
+
+// Ask if the target tables must be truncated, and if so, if on cascade
+fn ask_truncate(tables:&str) -> (bool, bool) {
+    let target_db_connection = &CONFIG_PROPERTIES.target;
+
+    let target_host_port = format!("{}:{}", target_db_connection.host, target_db_connection.port);
+
+    let confirm_msg = format!("Do you want to TRUNCATE {} in target DB [{}] ? (WARNING: ALL DATA WILL BE LOST!)", tables, target_host_port);
+
+    let truncate = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(confirm_msg.to_owned())
+        .default(false)
+        .interact()
+        .unwrap();
+
+    let mut cascade = false;
+    if truncate {
+        cascade = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("TRUNCATE on CASCADE ?")
+            .default(false)
+            .interact()
+            .unwrap();
+    }
+
+    (truncate, cascade)
+}
+
+fn ask_chunk_key(prompt:&str) -> Vec<String> {
+    let chunk_key_columns:String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .allow_empty(true)
+        .interact()
+        .unwrap();
+
+    chunk_key_columns.split(',').map(|column| column.trim().to_string()).filter(|column| !column.is_empty()).collect()
+}
+
+// For huge tables, a random sample of their rows or only the first ones in some order (e.g. the latest ones)
+fn ask_sample() -> (Option<f64>, i64, String, Option<i64>) {
+    let sample_percent = ask_number::<f64>("Percentage of rows to import as a random sample: [Optional, e.g. 1]");
+    let mut seed = None;
+    if sample_percent.is_some() {
+        seed = ask_number::<i64>("Seed for the sample, to get the same one again: [Optional, 0 by default]");
+    }
 
+    let limit = ask_number::<i64>("Max number of rows to import from each table: [Optional]");
+    let mut order_by = String::from("");
+    if limit.is_some() {
+        order_by = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("ORDER BY to choose the first rows: [Optional, e.g. created_at DESC]")
+            .allow_empty(true)
+            .interact()
+            .unwrap();
+    }
+
+    (sample_percent, seed.unwrap_or(0), order_by, limit)
+}
+
+fn ask_number<T>(prompt:&str) -> Option<T> where T: std::str::FromStr {
+    let number:String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .allow_empty(true)
+        .interact()
+        .unwrap();
+    if number.trim().is_empty() {
+        return None;
+    }
+
+    match number.trim().parse::<T>() {
+        Ok(number) => Some(number),
+        Err(_) => { println!("ERROR: {} isn't a valid number", number); std::process::exit(1); }
+    }
 }
 
 fn execute_interactive(){
@@ -53,6 +229,8 @@ fn execute_interactive(){
         std::process::exit(1);
     }
 
+    choose_databases();
+
     let schemas = db::get_available_schemas();
 
     let selection = Select::with_theme(&ColorfulTheme::default())
@@ -80,42 +258,168 @@ fn execute_interactive(){
         std::process::exit(1);
     }
 
+    // Partitioned tables can be imported partially, choosing some of their partitions
+    let mut selected_partitions:Vec<Vec<String>> = vec!();
+    for table_index in &selected_tables {
+        selected_partitions.push(choose_partitions_of(&selected_schema, &table_info_list[*table_index]));
+    }
+
+    // Rows of child tables are never read when importing their parent table, but they can be imported too, each to its own table
+    let mut selected_include_children:Vec<bool> = vec!();
+    for table_index in &selected_tables {
+        let table_info = &table_info_list[*table_index];
+        let mut include_children = false;
+        if table_info.children > 0 {
+            include_children = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("Table {} has child tables inheriting from it. Import also all of them?", table_info.name))
+                .default(false)
+                .interact()
+                .unwrap();
+        }
+        selected_include_children.push(include_children);
+    }
+
+    // Views, materialized views and foreign tables don't have any UNIQUE constraint to split their rows between threads
+    let mut selected_chunk_keys:Vec<Vec<String>> = vec!();
+    for table_index in &selected_tables {
+        let table_info = &table_info_list[*table_index];
+        let mut chunk_key = vec!();
+        if !table_info.is_table() && CONFIG_PROPERTIES.max_threads > 1 {
+            chunk_key = ask_chunk_key(format!("Columns with unique values to import {} {} in several threads: [Optional, comma separated]",
+                table_info.kind, table_info.name).as_str());
+        }
+        selected_chunk_keys.push(chunk_key);
+    }
+
     let where_clause:String = Input::with_theme(&ColorfulTheme::default())
     .with_prompt("WHERE: [Optional]")
     .allow_empty(true)
     .interact()
     .unwrap();
 
+    let (sample_percent, seed, order_by, limit) = ask_sample();
+
     let target_db_connection = &CONFIG_PROPERTIES.target;
 
     let target_host_port = format!("{}:{}", target_db_connection.host, target_db_connection.port);
 
-    let confirm_msg = format!("Do you want to TRUNCATE selected tables in target DB [{}] ? (WARNING: ALL DATA WILL BE LOST!)", target_host_port);
-
-    let truncate = Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt(confirm_msg.to_owned())
-        .default(false)
-        .interact()
-        .unwrap();
+    // Replacing only makes sense when importing a subset of the rows, whichever filter chooses them
+    let filtered = !where_clause.is_empty() || !CONFIG_PROPERTIES.filter_column.is_empty() || !CONFIG_PROPERTIES.keys_file.is_empty()
+        || !CONFIG_PROPERTIES.since.is_empty() || !CONFIG_PROPERTIES.between.is_empty();
+    let mut replace = false;
+    if filtered {
+        let replace_msg = format!("Do you want to REPLACE rows matching the filters in target DB [{}] ? (Matching rows will be DELETED before the import)", target_host_port);
 
-    let mut cascade = false;
-    if truncate {
-        cascade = Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt("TRUNCATE on CASCADE ?")
+        replace = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(replace_msg)
             .default(false)
             .interact()
             .unwrap();
     }
 
-    for table_index in selected_tables {
-        db::import_table_from(selected_schema.to_owned(),
-                              table_info_list[table_index].name.to_owned(),
-                              where_clause.to_owned(),
-                              truncate,
-                              cascade);
+    let mut truncate = false;
+    let mut cascade = false;
+    if !replace && !config::is_check_command() {
+        (truncate, cascade) = ask_truncate("selected tables");
+    }
+
+    let options = ImportOptions { where_clause, truncate, cascade, replace, create_missing: CONFIG_PROPERTIES.create_missing,
+        add_missing_columns: CONFIG_PROPERTIES.add_missing_columns, sample_percent, seed, order_by, limit,
+        time_column: CONFIG_PROPERTIES.time_column.to_owned(), masking_rules: masking::rules_from_file(&CONFIG_PROPERTIES.masking_file),
+        ..Default::default() };
+
+    // Child tables also chosen on their own are only imported once
+    let mut tables_to_import:Vec<(String, String, ImportOptions)> = vec!();
+    let table_choices = selected_partitions.into_iter().zip(selected_include_children).zip(selected_chunk_keys);
+    for (table_index, ((partitions, include_children), chunk_key)) in selected_tables.iter().zip(table_choices) {
+        let table_options = ImportOptions { partitions, include_children, chunk_key, ..options.clone() };
+        for (schema, table, options) in db::tables_to_import(&selected_schema, &table_info_list[*table_index].name, &table_options) {
+            if !tables_to_import.iter().any(|(imported_schema, imported_table, _)| imported_schema == &schema && imported_table == &table) {
+                tables_to_import.push((schema, table, options));
+            }
+        }
+    }
+
+    let tables_to_import = filter::filter_by_column(tables_to_import, &CONFIG_PROPERTIES.filter_column,
+        &CONFIG_PROPERTIES.tables_without_filter);
+    let tables_to_import = filter::filter_by_keys_file(tables_to_import, &CONFIG_PROPERTIES.keys_file, &CONFIG_PROPERTIES.key_column,
+        &CONFIG_PROPERTIES.tables_without_filter);
+    let tables_to_import = filter::filter_by_time_window(tables_to_import, &CONFIG_PROPERTIES.since, &CONFIG_PROPERTIES.between,
+        &CONFIG_PROPERTIES.tables_without_filter);
+    ddl::create_missing_objects(&tables_to_import);
+    let tables_to_import = check::check_tables(tables_to_import);
+    for (schema, table, table_options) in &tables_to_import {
+        db::check_where_clause(schema, table, table_options);
+        db::check_partitions(schema, table, table_options);
+        db::check_chunk_key(schema, table, table_options);
+        db::check_sample(schema, table, table_options);
+    }
+    utils::exit_if_dry_run();
+
+    foreign_keys::import_tables(tables_to_import);
+}
+
+// Any database of the source and target servers can be used, not only the ones in the connection URLs
+fn choose_databases() {
+    let url_source_database = config::get_source_database();
+    let url_target_database = config::get_target_database();
+
+    let source_databases = db::get_available_databases(config::get_source_db_url().as_str());
+    let source_selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Choose the source Database")
+        .default(source_databases.iter().position(|database| database == &url_source_database).unwrap_or(0))
+        .items(&source_databases[..])
+        .interact()
+        .unwrap();
+    let source_database = source_databases[source_selection].to_owned();
+
+    // By default, import to the database with the same name in the target server
+    let target_databases = db::get_available_databases(config::get_target_db_url().as_str());
+    let default_target = target_databases.iter().position(|database| database == &source_database)
+        .or_else(|| target_databases.iter().position(|database| database == &url_target_database))
+        .unwrap_or(0);
+    let target_selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Choose the target Database")
+        .default(default_target)
+        .items(&target_databases[..])
+        .interact()
+        .unwrap();
+    let target_database = target_databases[target_selection].to_owned();
+
+    if source_database != url_source_database || target_database != url_target_database {
+        config::use_databases(Some(source_database), Some(target_database));
+        if !utils::check_server_settings() {
+            std::process::exit(1);
+        }
     }
 }
 
+// Returns the names of the partitions chosen by the user, or none if all of them were chosen (or the table isn't partitioned)
+fn choose_partitions_of(schema:&str, table_info:&TableInfo) -> Vec<String> {
+    if table_info.partitions == 0 {
+        return vec!();
+    }
+
+    let partitions = db::get_partitions_of_table(schema, &table_info.name);
+    let partition_labels:Vec<String> = partitions.iter()
+        .map(|partition| format!("{} - {} - {} rows", partition.name, partition.bound, partition.rows))
+        .collect();
+    let defaults = vec![true; partitions.len()];
+
+    let prompt = format!("Choose partitions of {} to import", table_info.name);
+    let selected = create_options_with(&partition_labels[..], &defaults[..], prompt.as_str());
+
+    if selected.is_empty() {
+        println!("You must select at least one partition of {} to import", table_info.name);
+        std::process::exit(1);
+    }
+    if selected.len() == partitions.len() {
+        return vec!();
+    }
+
+    selected.into_iter().map(|index| partitions[index].name.to_owned()).collect()
+}
+
 fn create_options_with<T:ToString>(options:&[T], defaults:&[bool], prompt:&str) -> Vec<usize> {
 
     let selections_result = MultiSelect::with_theme(&ColorfulTheme::default())
@@ -139,9 +443,35 @@ fn get_tables_info(schema:&str, tables:Vec<String>) -> Vec<TableInfo> {
     let mut table_info_list = Vec::new();
 
     for table in tables {
-        let rows = db::get_number_of_rows_for(schema, table.as_str());
-        table_info_list.push(TableInfo{name: table, rows: rows});
+        let stats = db::get_table_stats_for(schema, table.as_str());
+        table_info_list.push(TableInfo{name: table, rows: stats.rows, size: stats.size, partitions: stats.partitions,
+            parent: stats.parent, children: stats.children, kind: stats.kind, depth: 0});
     }
 
-    return table_info_list;
+    sort_by_hierarchy(table_info_list)
+}
+
+// List every table right after the table it inherits from, so the inheritance hierarchy is shown
+fn sort_by_hierarchy(table_info_list:Vec<TableInfo>) -> Vec<TableInfo> {
+    let names:Vec<String> = table_info_list.iter().map(|table_info| table_info.name.to_owned()).collect();
+    let (mut pending, roots):(Vec<TableInfo>, Vec<TableInfo>) = table_info_list.into_iter()
+        .partition(|table_info| table_info.parent.as_ref().is_some_and(|parent| names.contains(parent)));
+
+    let mut sorted = vec!();
+    for root in roots {
+        add_with_children(root, 0, &mut pending, &mut sorted);
+    }
+
+    sorted
+}
+
+fn add_with_children(mut table_info:TableInfo, depth:usize, pending:&mut Vec<TableInfo>, sorted:&mut Vec<TableInfo>) {
+    table_info.depth = depth;
+    let name = table_info.name.to_owned();
+    sorted.push(table_info);
+
+    while let Some(index) = pending.iter().position(|child| child.parent.as_ref() == Some(&name)) {
+        let child = pending.remove(index);
+        add_with_children(child, depth + 1, pending, sorted);
+    }
 }
@@ -0,0 +1,488 @@
+use chrono::{Duration, NaiveDate};
+use hmac::{Hmac, Mac};
+use postgres::{Client, NoTls};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{from_reader, Value};
+use sha2::Sha256;
+use std::collections::hash_map::RandomState;
+use std::convert::TryInto;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::BufReader;
+
+use crate::config;
+use crate::config::{CONFIG_PROPERTIES, ImportConfig};
+use crate::sql;
+
+const TRANSFORMS:[&str; 8] = ["null", "fixed", "hash", "fake_email", "fake_name", "fake_phone", "shuffle", "date_jitter"];
+
+// Types of the integers hashed to integers (as written by format_type)
+const INTEGER_TYPES:[&str; 3] = ["smallint", "integer", "bigint"];
+
+const FIRST_NAMES:[&str; 32] = ["Alice", "Bruno", "Carla", "David", "Elena", "Felix", "Grace", "Hugo", "Irene", "Jonas", "Karen", "Lucas",
+    "Maria", "Nicolas", "Olga", "Pablo", "Queralt", "Rosa", "Samuel", "Teresa", "Ulises", "Vera", "Walter", "Ximena", "Yago", "Zoe",
+    "Adrian", "Beatriz", "Carlos", "Diana", "Emilio", "Fatima"];
+const LAST_NAMES:[&str; 32] = ["Smith", "Garcia", "Muller", "Rossi", "Dubois", "Silva", "Novak", "Jensen", "Kowalski", "Ivanov", "Nielsen",
+    "Santos", "Moreau", "Fischer", "Romano", "Lopez", "Martin", "Bauer", "Costa", "Horvat", "Berg", "Lindqvist", "Petrov", "Ortega",
+    "Meyer", "Ricci", "Navarro", "Weber", "Ferrari", "Moreno", "Keller", "Torres"];
+
+// Rule to mask the values of some columns: the one with the given name, or the ones whose name matches a regular expression,
+// in the given table (table or schema.table) or in all of them
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MaskingRule {
+    pub table: Option<String>,
+    pub column: Option<String>,
+    pub column_pattern: Option<String>,
+    // null, fixed, hash, fake_email, fake_name, fake_phone, shuffle or date_jitter
+    pub transform: String,
+    // Value written by fixed
+    pub value: Option<Value>,
+    // Max number of days date_jitter moves dates, earlier or later
+    pub days: Option<i64>
+}
+
+// Column masked before writing it to target, by its position in the column lists, with the type and max length
+// (of varchar(n) and char(n) columns) of the target column
+#[derive(Clone)]
+pub struct MaskedColumn {
+    pub index: usize,
+    pub rule: MaskingRule,
+    pub data_type: String,
+    pub max_length: Option<usize>
+}
+
+// Column of another table linked to a column of a table by a foreign key of the source DB
+struct LinkedColumn {
+    schema: String,
+    table: String,
+    column: String
+}
+
+pub fn rules_from_file(masking_file:&str) -> Vec<MaskingRule> {
+    if masking_file.is_empty() {
+        return vec!();
+    }
+
+    let rules:Vec<MaskingRule> = match File::open(masking_file) {
+        Ok(file) => match from_reader(BufReader::new(file)) {
+            Ok(rules) => rules,
+            Err(error) => { println!("ERROR: Couldn't parse masking file {} : {}", masking_file, error); std::process::exit(1); }
+        },
+        Err(error) => { println!("ERROR: Couldn't open masking file {} : {}", masking_file, error); std::process::exit(1); }
+    };
+    check_rules(&rules);
+
+    rules
+}
+
+// Check the rules before anything is imported, as a wrong one could leave real data unmasked
+pub fn check_rules(rules:&[MaskingRule]) {
+    for (i, rule) in rules.iter().enumerate() {
+        if !TRANSFORMS.contains(&rule.transform.as_str()) {
+            println!("ERROR: Invalid transform in masking rule {}: {}. Valid values are {}", i, rule.transform, TRANSFORMS.join(", "));
+            std::process::exit(1);
+        }
+        if rule.column.is_some() == rule.column_pattern.is_some() {
+            println!("ERROR: Masking rule {} needs either a column or a column_pattern", i);
+            std::process::exit(1);
+        }
+        if let Some(column_pattern) = &rule.column_pattern {
+            if let Err(error) = Regex::new(column_pattern) {
+                println!("ERROR: Invalid column_pattern in masking rule {}: {} | Error: {}", i, column_pattern, error);
+                std::process::exit(1);
+            }
+        }
+        if rule.transform == "fixed" && rule.value.is_none() {
+            println!("ERROR: Masking rule {} needs the value to write in the masked columns", i);
+            std::process::exit(1);
+        }
+        if rule.transform == "fixed" && fixed_value(rule).is_none() {
+            println!("ERROR: The value of masking rule {} must be a string, a number, a boolean or null", i);
+            std::process::exit(1);
+        }
+        if rule.transform == "date_jitter" && rule.days.unwrap_or(0) <= 0 {
+            println!("ERROR: Masking rule {} needs the max number of days to move the dates, e.g. days: 30", i);
+            std::process::exit(1);
+        }
+    }
+
+    if !rules.is_empty() && CONFIG_PROPERTIES.masking_key.is_empty() {
+        println!("WARNING: No masking key given. Hashed and fake values could be guessed by masking known values. Set MASKING_KEY to avoid it");
+    }
+}
+
+// Columns of the table masked by the rules, by their position in the target columns. A column without a rule of its own is masked
+// with the rule of the column it references through a foreign key (or of a column referencing it), so keys still match once masked
+// Each target column comes with its type and max length, as masked values must still fit in it
+pub fn masked_columns_of(schema:&str, table:&str, target_columns:&[String], target_types:&[(String, Option<i32>)], rules:&[MaskingRule])
+    -> Vec<MaskedColumn> {
+    if rules.is_empty() {
+        return vec!();
+    }
+
+    let linked_columns = get_linked_columns(schema, table);
+    let mut masked_columns = vec!();
+    for (i, column) in target_columns.iter().enumerate() {
+        if let Some(rule) = rule_for(rules, schema, table, column) {
+            println!("INFO: Masking column {} of {}.{} with {}", column, schema, table, rule.transform);
+            masked_columns.push(masked_column(schema, table, column, i, rule, &target_types[i]));
+            continue;
+        }
+
+        let linked_rules = linked_columns.iter()
+            .filter(|(linked_column, _)| linked_column == column)
+            .filter_map(|(_, linked)| rule_for(rules, &linked.schema, &linked.table, &linked.column).map(|rule| (linked, rule)));
+        for (linked, rule) in linked_rules {
+            // Shuffled values can't be matched by the ones of another table
+            if rule.transform == "shuffle" {
+                println!("WARNING: Column {} of {}.{} is linked by a foreign key to the shuffled column {} of {}.{}. Their values won't match",
+                    column, schema, table, linked.column, linked.schema, linked.table);
+                continue;
+            }
+            println!("INFO: Masking column {} of {}.{} with {}, as column {} of {}.{} linked by a foreign key", column, schema, table,
+                rule.transform, linked.column, linked.schema, linked.table);
+            masked_columns.push(masked_column(schema, table, column, i, rule, &target_types[i]));
+            break;
+        }
+    }
+
+    masked_columns
+}
+
+// Hashed values must be valid values of the target column, so only the types they can be written to can be hashed
+fn masked_column(schema:&str, table:&str, column:&str, index:usize, rule:&MaskingRule, target_type:&(String, Option<i32>)) -> MaskedColumn {
+    let (data_type, max_length) = target_type;
+    if rule.transform == "hash" && !is_hashable(data_type) {
+        println!("ERROR: Column {} of {}.{} can't be hashed, as hashes can't be written to type {}. Use another transform for it",
+            column, schema, table, data_type);
+        std::process::exit(1);
+    }
+
+    MaskedColumn { index, rule: rule.to_owned(), data_type: data_type.to_owned(), max_length: max_length.map(|length| length as usize) }
+}
+
+fn is_hashable(data_type:&str) -> bool {
+    INTEGER_TYPES.contains(&data_type) || data_type == "uuid" || is_text_type(data_type)
+}
+
+fn is_text_type(data_type:&str) -> bool {
+    ["text", "character varying", "character", "name", "citext"].contains(&data_type) || data_type.ends_with(".citext")
+}
+
+// The first rule matching the column is the one used
+fn rule_for<'a>(rules:&'a [MaskingRule], schema:&str, table:&str, column:&str) -> Option<&'a MaskingRule> {
+    rules.iter().find(|rule| {
+        let table_matches = match &rule.table {
+            Some(rule_table) => match rule_table.split_once('.') {
+                Some((rule_schema, rule_table)) => rule_schema == schema && rule_table == table,
+                None => rule_table == table
+            },
+            None => true
+        };
+        let column_matches = match (&rule.column, &rule.column_pattern) {
+            (Some(rule_column), _) => rule_column == column,
+            (None, Some(column_pattern)) => Regex::new(column_pattern).unwrap().is_match(column),
+            (None, None) => false
+        };
+        table_matches && column_matches
+    })
+}
+
+// Columns of the table (by name) in a foreign key, with the column of the other table they're linked to. Keys are read from
+// the source DB, as the ones in a cycle are dropped from the target DB while importing
+fn get_linked_columns(schema:&str, table:&str) -> Vec<(String, LinkedColumn)> {
+    let mut source_client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+
+    // Both ways: the columns referencing other tables, and the ones referenced by other tables
+    let linked_columns_query = "SELECT this_column.attname::text, other_ns.nspname::text, other.relname::text, other_column.attname::text
+        FROM pg_constraint pgco
+        JOIN LATERAL (SELECT pgco.conrelid AS this_oid, pgco.conkey AS this_keys, pgco.confrelid AS other_oid, pgco.confkey AS other_keys
+            UNION ALL SELECT pgco.confrelid, pgco.confkey, pgco.conrelid, pgco.conkey) link ON true
+        JOIN pg_class this ON this.oid = link.this_oid
+        JOIN pg_namespace this_ns ON this_ns.oid = this.relnamespace
+        JOIN pg_class other ON other.oid = link.other_oid
+        JOIN pg_namespace other_ns ON other_ns.oid = other.relnamespace
+        JOIN generate_subscripts(link.this_keys, 1) i ON true
+        JOIN pg_attribute this_column ON this_column.attrelid = link.this_oid AND this_column.attnum = link.this_keys[i]
+        JOIN pg_attribute other_column ON other_column.attrelid = link.other_oid AND other_column.attnum = link.other_keys[i]
+        WHERE pgco.contype = 'f' AND this_ns.nspname = $1 AND this.relname = $2
+        ORDER BY pgco.conname, i";
+
+    source_client.query(linked_columns_query, &[&schema, &table]).unwrap().iter()
+        .map(|row| (row.get(0), LinkedColumn { schema: row.get(1), table: row.get(2), column: row.get(3) }))
+        .collect()
+}
+
+// Mask the values of the masked columns in a buffer of rows in COPY text format, returning the same rows with the masked values.
+// Shuffled values are moved between the rows of the buffer only: rows are masked as they're streamed, so the values of a column
+// are never all read at once, and a value can't end in a row of another buffer
+pub fn mask_buffer(import_config:&ImportConfig, buffer:&[u8]) -> Vec<u8> {
+    // Rows end with a new line and their values are separated by tabs (those inside values are escaped)
+    let mut rows:Vec<Vec<Vec<u8>>> = buffer.split(|byte| *byte == b'\n').filter(|row| !row.is_empty())
+        .map(|row| row.split(|byte| *byte == b'\t').map(|value| value.to_vec()).collect())
+        .collect();
+
+    let random_state = RandomState::new();
+    let key = CONFIG_PROPERTIES.masking_key.as_bytes();
+    for masked_column in &import_config.masked_columns {
+        let column_index = &masked_column.index;
+        if masked_column.rule.transform == "shuffle" {
+            let mut order:Vec<(u64, usize)> = (0..rows.len()).map(|i| {
+                let mut hasher = random_state.build_hasher();
+                hasher.write_usize(i);
+                (hasher.finish(), i)
+            }).collect();
+            order.sort();
+            let values:Vec<Vec<u8>> = order.iter().map(|(_, i)| rows[*i][*column_index].to_owned()).collect();
+            for (row, value) in rows.iter_mut().zip(values) {
+                row[*column_index] = value;
+            }
+            continue;
+        }
+
+        for row in rows.iter_mut() {
+            row[*column_index] = mask_value(key, masked_column, &row[*column_index]);
+        }
+    }
+
+    let mut new_buffer = vec!();
+    for row in rows {
+        new_buffer.extend(row.join(&b'\t'));
+        new_buffer.push(b'\n');
+    }
+
+    new_buffer
+}
+
+// Mask a value of a column in COPY text format. NULL values are kept, as there's nothing to hide in them
+fn mask_value(key:&[u8], masked_column:&MaskedColumn, value:&[u8]) -> Vec<u8> {
+    let rule = &masked_column.rule;
+    if value == b"\\N" {
+        return value.to_vec();
+    }
+
+    let masked_value = match rule.transform.as_str() {
+        "null" => String::from("\\N"),
+        "fixed" => fixed_value(rule).unwrap_or_default(),
+        "hash" => hash_value(key, &masked_column.data_type, value),
+        "fake_email" => {
+            let bytes = digest(key, value, 6);
+            format!("{}.{}.{:02x}{:02x}{:02x}{:02x}@example.com", FIRST_NAMES[bytes[0] as usize % FIRST_NAMES.len()].to_lowercase(),
+                LAST_NAMES[bytes[1] as usize % LAST_NAMES.len()].to_lowercase(), bytes[2], bytes[3], bytes[4], bytes[5])
+        },
+        "fake_name" => {
+            let bytes = digest(key, value, 2);
+            let first_name = FIRST_NAMES[bytes[0] as usize % FIRST_NAMES.len()];
+            // A single word is masked as a single name
+            if String::from_utf8_lossy(value).trim().contains(' ') {
+                format!("{} {}", first_name, LAST_NAMES[bytes[1] as usize % LAST_NAMES.len()])
+            }
+            else {
+                first_name.to_string()
+            }
+        },
+        // Every digit is replaced, keeping the rest of characters (e.g. +, spaces or the country code of an IBAN) where they are
+        "fake_phone" => {
+            let value = String::from_utf8_lossy(value);
+            let bytes = digest(key, value.as_bytes(), value.chars().count());
+            value.chars().zip(bytes).map(|(c, random)| if c.is_ascii_digit() { (b'0' + random % 10) as char } else { c }).collect()
+        },
+        "date_jitter" => jitter_date(key, rule.days.unwrap_or(0), value),
+        _ => String::from("\\N")
+    };
+
+    // Made-up values must fit in the column too
+    match masked_column.max_length {
+        Some(max_length) if rule.transform != "fixed" => masked_value.chars().take(max_length).collect::<String>().into_bytes(),
+        _ => masked_value.into_bytes()
+    }
+}
+
+// Hashes keep the type of the column: integers are hashed to integers of the same size (and sign), so they still fit in the column
+// and different keys still get different hashes, uuids to uuids and texts to hex characters
+fn hash_value(key:&[u8], data_type:&str, value:&[u8]) -> String {
+    if INTEGER_TYPES.contains(&data_type) {
+        return match String::from_utf8_lossy(value).parse::<i64>() {
+            Ok(integer) => hash_integer(key, integer).to_string(),
+            Err(_) => String::from("\\N")
+        };
+    }
+
+    let hex:String = digest(key, value, 32).iter().map(|byte| format!("{:02x}", byte)).collect();
+    if data_type == "uuid" {
+        format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+    }
+    else {
+        hex
+    }
+}
+
+// Integers are moved to another integer of the same range: smallint values, integer values that aren't smallint ones, and bigint
+// values that aren't integer ones. So the same value gets the same hash whatever the size of the column it's in (keys still match)
+fn hash_integer(key:&[u8], value:i64) -> i64 {
+    // Negative values are hashed as their absolute value minus one, so they keep their sign
+    if value < 0 {
+        return -1 - hash_integer(key, -1 - value);
+    }
+
+    let value = value as u64;
+    let (start, end) = if value < 1 << 15 { (0, 1 << 15) } else if value < 1 << 31 { (1 << 15, 1 << 31) } else { (1 << 31, 1 << 63) };
+
+    (start + permute(key, value - start, end - start)) as i64
+}
+
+// Keyed permutation of the numbers from 0 to size - 1 (a Feistel network over the bits needed for them). Numbers outside
+// the range are permuted again until they're inside it, which keeps the permutation over the range
+fn permute(key:&[u8], value:u64, size:u64) -> u64 {
+    let half_bits = (64 - (size - 1).leading_zeros()).div_ceil(2);
+    let half_mask = (1u64 << half_bits) - 1;
+
+    let mut permuted = value;
+    loop {
+        let mut left = permuted >> half_bits;
+        let mut right = permuted & half_mask;
+        for round in 0..4u8 {
+            let mut round_value = vec!(round);
+            round_value.extend(&right.to_be_bytes());
+            let bytes = digest(key, &round_value, 8);
+            let new_right = left ^ (u64::from_be_bytes(bytes.try_into().unwrap()) & half_mask);
+            left = right;
+            right = new_right;
+        }
+        permuted = (left << half_bits) | right;
+        if permuted < size {
+            return permuted;
+        }
+    }
+}
+
+// Dates, timestamps and timestamps with time zone start with the date (DateStyle is ISO in all sessions). The same value is always
+// moved the same number of days. Values that aren't dates are set to NULL, as they couldn't be masked
+fn jitter_date(key:&[u8], days:i64, value:&[u8]) -> String {
+    let value = String::from_utf8_lossy(value).to_string();
+    if value == "infinity" || value == "-infinity" {
+        return value;
+    }
+
+    let date = match value.get(0..10).and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()) {
+        Some(date) => date,
+        None => return String::from("\\N")
+    };
+    let bytes = digest(key, value.as_bytes(), 8);
+    let shift = (u64::from_be_bytes(bytes.try_into().unwrap()) % (2 * days as u64 + 1)) as i64 - days;
+
+    format!("{}{}", (date + Duration::days(shift)).format("%Y-%m-%d"), &value[10..])
+}
+
+// Deterministic bytes for the value: the same value gets the same bytes in every table and every import with the same masking key
+fn digest(key:&[u8], value:&[u8], length:usize) -> Vec<u8> {
+    let mut bytes = vec!();
+    let mut block:u32 = 0;
+    while bytes.len() < length {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(&block.to_be_bytes());
+        mac.update(value);
+        bytes.extend(mac.finalize().into_bytes());
+        block += 1;
+    }
+    bytes.truncate(length);
+
+    bytes
+}
+
+// Fixed values are written in COPY text format, escaping the characters with a special meaning in it
+fn fixed_value(rule:&MaskingRule) -> Option<String> {
+    let value = match rule.value.as_ref()? {
+        Value::String(s) => s.to_owned(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => return Some(String::from("\\N")),
+        _ => return None
+    };
+
+    Some(sql::escape_copy_text(&value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY:&[u8] = b"test key";
+
+    fn masked(transform:&str, data_type:&str, max_length:Option<usize>, value:&str) -> String {
+        let rule = MaskingRule { table: None, column: Some(String::from("c")), column_pattern: None, transform: transform.to_string(),
+            value: None, days: Some(30) };
+        let masked_column = MaskedColumn { index: 0, rule, data_type: data_type.to_string(), max_length };
+        String::from_utf8(mask_value(KEY, &masked_column, value.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn digest_is_deterministic_and_keyed() {
+        assert_eq!(digest(KEY, b"value", 40), digest(KEY, b"value", 40));
+        assert_eq!(digest(KEY, b"value", 40).len(), 40);
+        assert_eq!(digest(KEY, b"value", 8), digest(KEY, b"value", 40)[0..8]);
+        assert_ne!(digest(KEY, b"value", 8), digest(KEY, b"other", 8));
+        assert_ne!(digest(KEY, b"value", 8), digest(b"other key", b"value", 8));
+    }
+
+    #[test]
+    fn jitter_date_moves_dates_up_to_the_days() {
+        for day in 1..=28 {
+            let value = format!("2026-02-{:02}", day);
+            let jittered = NaiveDate::parse_from_str(&jitter_date(KEY, 5, value.as_bytes()), "%Y-%m-%d").unwrap();
+            let date = NaiveDate::parse_from_str(&value, "%Y-%m-%d").unwrap();
+            assert!((jittered - date).num_days().abs() <= 5);
+        }
+        assert_eq!(jitter_date(KEY, 5, b"2026-02-01"), jitter_date(KEY, 5, b"2026-02-01"));
+    }
+
+    #[test]
+    fn jitter_date_keeps_the_time() {
+        assert!(jitter_date(KEY, 5, b"2026-02-01 10:30:00+02").ends_with(" 10:30:00+02"));
+        assert_eq!(jitter_date(KEY, 5, b"infinity"), "infinity");
+        assert_eq!(jitter_date(KEY, 5, b"not a date"), "\\N");
+    }
+
+    #[test]
+    fn hashed_integers_keep_their_range_and_sign() {
+        let mut hashes = std::collections::HashSet::new();
+        for value in 0..2000 {
+            let hash = hash_integer(KEY, value);
+            assert!((0..1 << 15).contains(&hash));
+            hashes.insert(hash);
+        }
+        assert_eq!(hashes.len(), 2000);
+        assert!((1 << 15..1 << 31).contains(&hash_integer(KEY, 100000)));
+        assert!(hash_integer(KEY, i64::MAX) >= 1 << 31);
+        assert!((-(1 << 15)..0).contains(&hash_integer(KEY, -1)));
+        assert!(hash_integer(KEY, i64::MIN) < -(1 << 31));
+        assert_eq!(masked("hash", "bigint", None, "42"), masked("hash", "smallint", None, "42"));
+    }
+
+    #[test]
+    fn hashes_keep_the_type() {
+        let uuid = masked("hash", "uuid", None, "6f1c0a52-8a8e-4b7e-9d3e-0c8c3e1d2a10");
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(uuid.matches('-').count(), 4);
+        assert_eq!(masked("hash", "text", None, "secret").len(), 64);
+        assert_eq!(masked("hash", "character varying", Some(10), "secret").len(), 10);
+        assert_eq!(masked("hash", "character", Some(3), "secret").len(), 3);
+        assert_eq!(masked("fake_email", "character varying", Some(8), "a@b.com").len(), 8);
+        assert_eq!(masked("hash", "integer", None, "\\N"), "\\N");
+    }
+
+    #[test]
+    fn fake_phone_keeps_non_digits() {
+        let phone = masked("fake_phone", "text", None, "+34 612 345 678");
+        assert_eq!(phone.len(), 15);
+        assert!(phone.starts_with("+"));
+        assert_eq!(phone.matches(' ').count(), 3);
+        let phone = masked("fake_phone", "text", None, "Tél. 01 23");
+        assert!(phone.starts_with("Tél. "));
+        assert_eq!(phone.chars().count(), 10);
+    }
+}
@@ -6,7 +6,11 @@ use indicatif::{ProgressBar, ProgressStyle, MultiProgress};
 
 use crate::copy::CopyImporter;
 use crate::query::QueryImporter;
+use crate::db;
 use crate::db::{DBClients, TableChunk, TableImporter};
+use crate::filter;
+use crate::large_objects;
+use crate::sql;
 
 pub fn multi_thread_import(import_config:&Arc<ImportConfig>, order_by:&String, total_rows_to_import:i64) {
 
@@ -16,6 +20,9 @@ pub fn multi_thread_import(import_config:&Arc<ImportConfig>, order_by:&String, t
     // Divide all rows to import by the number of threads to use
     let rows_per_thread = total_rows_to_import / max_threads;
 
+    // All threads read the same version of the table, even if it's changed while importing
+    let (snapshot_client, snapshot_id) = db::export_source_snapshot(&import_config.source_db_url);
+
     // START IMPORTING, SPAWNING WORKER THREADS
     // Create the progression bars
     let multi_progress_bar = MultiProgress::new();
@@ -24,6 +31,7 @@ pub fn multi_thread_import(import_config:&Arc<ImportConfig>, order_by:&String, t
         .progress_chars("##-");
 
     let mut previous_thread_last_row = 0;
+    let mut threads = vec!();
     // Remember that higher limit in for loop is exclusive in Rust so this is actually 0 to max_threads-1:
     for thread_num in 0..max_threads {
     
@@ -54,9 +62,10 @@ pub fn multi_thread_import(import_config:&Arc<ImportConfig>, order_by:&String, t
         // Those references will be removed when the thread ends and when there are no references left the memory will be freed
         let import_config = import_config.clone();
         let order_by = order_by.clone();
+        let snapshot_id = snapshot_id.clone();
 
         // NEW WORKER THREAD BEGINS
-        thread::spawn(move || {
+        threads.push(thread::spawn(move || {
             
             let source_client = match Client::connect(import_config.source_db_url.as_ref(), NoTls) {
                 Ok(client) => client,
@@ -68,14 +77,15 @@ pub fn multi_thread_import(import_config:&Arc<ImportConfig>, order_by:&String, t
                 Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
             };
 
-            let mut db_clients = DBClients { source_client: source_client, target_client: target_client};
+            let large_objects_client = large_objects::large_objects_client_for(&import_config, &snapshot_id);
+            let mut db_clients = DBClients { source_client: source_client, target_client: target_client, large_objects_client };
+
+            filter::use_keys_tables(&mut db_clients.source_client);
+            db::use_source_snapshot(&mut db_clients.source_client, &snapshot_id);
 
             let mut rows_read_in_this_thread = 0;
             // Create select query
-            let mut complete_where:String = import_config.where_clause.to_owned().to_string();
-            if !import_config.where_clause.is_empty() {
-                complete_where = format!("WHERE {}", import_config.where_clause);
-            }
+            let complete_where = sql::where_section(&import_config.source_where_clause);
 
             // If number of rows to read in this thread are more than MAX_ROWS_FOR_SELECT, divide in several selects of max size
             // Doing this is specially important for big queries, as the memory consumption could even kill the process
@@ -94,13 +104,18 @@ pub fn multi_thread_import(import_config:&Arc<ImportConfig>, order_by:&String, t
                 let table_chunk = TableChunk { where_clause: complete_where.to_owned(), offset: offset, 
                     limit: limit, order_by: order_by.to_owned()};
 
-                if import_config.importer_impl == "QUERY" {
+                let result = if import_config.importer_impl == "QUERY" {
                     let importer = QueryImporter;                    
-                    importer.import_table_chunk(&import_config, &mut db_clients, &table_chunk);
+                    importer.import_table_chunk(&import_config, &mut db_clients, &table_chunk)
                 }
                 else {
                     let importer = CopyImporter;
-                    importer.import_table_chunk(&import_config, &mut db_clients, &table_chunk);
+                    importer.import_table_chunk(&import_config, &mut db_clients, &table_chunk)
+                };
+                if let Err(error) = result {
+                    println!("ERROR: Couldn't import rows {} to {} of table {}.{} | Error: {}", offset, offset + limit,
+                        import_config.schema, import_config.table, error);
+                    std::process::exit(1);
                 }
  
                 // Update progress bar after execution
@@ -120,10 +135,17 @@ pub fn multi_thread_import(import_config:&Arc<ImportConfig>, order_by:&String, t
             progress_bar.finish_with_message(
                 format!("Thread {} finished reading rows from {} to {}",thread_num, offset_for_this_thread, max_offset).as_str());
             return limit_for_this_thread;
-        });
+        }));
 
     }
 
     // Wait for all the progress bars to finish. Also acts as a join for the child threads
     multi_progress_bar.join_and_clear().unwrap();
+    // A thread that panicked didn't import all its rows
+    if threads.into_iter().any(|thread| thread.join().is_err()) {
+        println!("ERROR: A thread importing table {}.{} failed. Aborting import", import_config.schema, import_config.table);
+        std::process::exit(1);
+    }
+
+    drop(snapshot_client);
 }
@@ -0,0 +1,86 @@
+use crate::config::{ImportConfig, CONFIG_PROPERTIES};
+use postgres::{Client, NoTls};
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use indicatif::{ProgressBar, ProgressStyle, MultiProgress};
+
+use crate::db;
+use crate::filter;
+use crate::large_objects;
+use crate::single_import;
+
+// Import the partitions of a partitioned table in parallel, with one COPY stream per partition.
+// Each worker thread keeps taking the next pending partition until all of them are imported
+pub fn partitioned_import(partition_imports:Vec<(ImportConfig, i64)>) {
+    let max_threads = cmp::max(1, cmp::min(CONFIG_PROPERTIES.max_threads as usize, partition_imports.len()));
+    let source_db_url = partition_imports[0].0.source_db_url.to_owned();
+
+    // All partitions are read from the same version of the source DB, even if it's changed while importing
+    let (snapshot_client, snapshot_id) = db::export_source_snapshot(&source_db_url);
+
+    // Create the progression bars, one per partition
+    let multi_progress_bar = MultiProgress::new();
+    let sty = ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+        .progress_chars("##-");
+
+    let mut pending_partitions = VecDeque::new();
+    for (partition_config, partition_rows) in partition_imports {
+        let progress_bar = multi_progress_bar.add(ProgressBar::new(partition_rows as u64));
+        progress_bar.set_style(sty.clone());
+        progress_bar.set_message(format!("Partition {}", partition_config.source_table).as_str());
+        pending_partitions.push_back((partition_config, progress_bar));
+    }
+    let pending_partitions = Arc::new(Mutex::new(pending_partitions));
+
+    let mut threads = vec!();
+    for _ in 0..max_threads {
+        let pending_partitions = pending_partitions.clone();
+        let source_db_url = source_db_url.clone();
+        let snapshot_id = snapshot_id.clone();
+
+        threads.push(thread::spawn(move || {
+            let mut source_client = match Client::connect(source_db_url.as_ref(), NoTls) {
+                Ok(client) => client,
+                Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+            };
+
+            filter::use_keys_tables(&mut source_client);
+            db::use_source_snapshot(&mut source_client, &snapshot_id);
+            let mut large_objects_client = None;
+
+            loop {
+                let next_partition = pending_partitions.lock().unwrap().pop_front();
+                let (partition_config, progress_bar) = match next_partition {
+                    Some(next_partition) => next_partition,
+                    None => break
+                };
+
+                let mut target_client = match Client::connect(partition_config.target_db_url.as_ref(), NoTls) {
+                    Ok(client) => client,
+                    Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
+                };
+
+                if large_objects_client.is_none() {
+                    large_objects_client = large_objects::large_objects_client_for(&partition_config, &snapshot_id);
+                }
+                let rows = single_import::copy_rows(&partition_config, &mut source_client, &mut target_client, &mut large_objects_client,
+                    &progress_bar);
+                progress_bar.finish_with_message(
+                    format!("Finished reading {} rows from partition {}", rows, partition_config.source_table).as_str());
+            }
+        }));
+    }
+
+    // Wait for all the progress bars to finish. Also acts as a join for the child threads
+    multi_progress_bar.join_and_clear().unwrap();
+    // A thread that panicked didn't import all its partitions
+    if threads.into_iter().any(|thread| thread.join().is_err()) {
+        println!("ERROR: A thread importing partitions failed. Aborting import");
+        std::process::exit(1);
+    }
+
+    drop(snapshot_client);
+}
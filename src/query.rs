@@ -1,170 +1,161 @@
-use chrono::offset::Utc;
-use chrono::DateTime;
-use postgres::types::Type;
-use postgres::{Column, Error, Row};
-use rust_decimal::Decimal;
-use std::time::SystemTime;
+use bytes::BytesMut;
+use postgres::types::{FromSql, IsNull, ToSql, Type, to_sql_checked};
+use postgres::{Client, Statement};
+use std::borrow::Cow;
+use std::cmp;
+use std::convert::TryInto;
+use std::error::Error;
 
 use crate::config::{CONFIG_PROPERTIES, ImportConfig};
 use crate::db::{DBClients, TableChunk, TableImporter};
-use crate::utils::log_error;
+use crate::large_objects;
+use crate::sql;
+
+// Postgres doesn't accept more parameters than this on a single statement
+const MAX_PARAMETERS_PER_STATEMENT:usize = 65535;
 
 pub struct QueryImporter;
 
+// Any value read from the source DB, kept in its binary format so it's written as it is in the target DB,
+// whatever its type is (arrays, enums, jsonb, uuid...)
+#[derive(Debug)]
+struct RawValue<'a>(Option<Cow<'a, [u8]>>);
+
+impl<'a> FromSql<'a> for RawValue<'a> {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(RawValue(Some(Cow::Borrowed(raw))))
+    }
+
+    fn from_sql_null(_ty: &Type) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(RawValue(None))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+impl ToSql for RawValue<'_> {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        match &self.0 {
+            Some(raw) => {
+                out.extend_from_slice(raw);
+                Ok(IsNull::No)
+            }
+            None => Ok(IsNull::Yes),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
 impl TableImporter for QueryImporter {
 
-    fn import_table_chunk(&self, import_config: &ImportConfig, db_clients:&mut DBClients, chunk: &TableChunk) {
-        let min_rows_for_insert = CONFIG_PROPERTIES.rows_insert;
+    fn import_table_chunk(&self, import_config: &ImportConfig, db_clients:&mut DBClients, chunk: &TableChunk) -> Result<(), Box<dyn std::error::Error>> {
+        let columns_per_row = import_config.target_columns.len();
+        let rows_per_insert = cmp::max(1, cmp::min(CONFIG_PROPERTIES.rows_insert as usize, MAX_PARAMETERS_PER_STATEMENT / columns_per_row));
 
-        let mut rows_read_in_this_thread = 0;
-        let mut column_names: Vec<String> = vec![];
-        let mut query_values: String = String::from("");
-        let mut rows_to_insert: i64 = 0;
+        // Values whose binary format may not be the one of the target column are read as text
+        let select_columns: Vec<String> = import_config.source_columns.iter().enumerate()
+            .map(|(column_index, column)| match import_config.text_columns.iter().any(|(text_column, _)| *text_column == column_index) {
+                true => format!("({})::text", column),
+                false => column.to_owned()
+            })
+            .collect();
 
         // Create the SELECT query for this iteration
         let select_query = format!(
-            "SELECT * FROM {}.{} {} ORDER BY {} OFFSET {} LIMIT {}",
-            import_config.schema,
-            import_config.table,
+            "SELECT {} FROM {} {} ORDER BY {} OFFSET {} LIMIT {}",
+            select_columns.join(", "),
+            import_config.source_table,
             chunk.where_clause,
             chunk.order_by,
             chunk.offset,
             chunk.limit
         );
 
-        // Read all values for previous query and insert them in the target DB
-        for row in db_clients.source_client.query(select_query.as_str(), &[]).unwrap() {
-            rows_read_in_this_thread += 1;
+        let rows = db_clients.source_client.query(select_query.as_str(), &[])?;
 
-            // Column names are always the same, do this only once
-            if column_names.is_empty() {
-                for column in row.columns() {
-                    let column_name = column.name();
-                    column_names.push(column_name.to_string());
-                }
-            }
+        // All the INSERTs but the last one have the same number of rows, so that statement is prepared only once
+        let mut full_insert_statement: Option<Statement> = None;
 
-            let mut column_values: Vec<String> = vec![];
-            // Get a whole row of values
-            for column in row.columns() {
-                let value: String = string_value_for(&row, column);
-                column_values.push(value);
-            }
-            rows_to_insert = rows_to_insert + 1;
-
-            // Create as many values as needed for this row
-            let mut row_values = format!("({}", column_values[0]);
-            for i in 1..column_values.len() {
-                row_values = format!("{},{}", row_values, column_values[i]);
+        for rows_to_insert in rows.chunks(rows_per_insert) {
+            let mut values: Vec<RawValue> = Vec::with_capacity(rows_to_insert.len() * columns_per_row);
+            for row in rows_to_insert {
+                for column_index in 0..columns_per_row {
+                    let mut value: RawValue = row.get(column_index);
+                    if import_config.large_object_columns.contains(&column_index) {
+                        value = copy_large_object_for(import_config, db_clients.large_objects_client.as_mut().unwrap(), &mut db_clients.target_client, value)?;
+                    }
+                    values.push(value);
+                }
             }
-            row_values = format!("{})", row_values);
 
-            // Add new row to insert to the query values
-            if query_values.is_empty() {
-                query_values = row_values;
-            } else {
-                query_values = format!("{},{}", query_values, row_values);
+            let statement = if rows_to_insert.len() == rows_per_insert && full_insert_statement.is_some() {
+                full_insert_statement.clone().unwrap()
             }
+            else {
+                let insert_query = insert_query_for(import_config, rows_to_insert.len());
+                let statement = db_clients.target_client.prepare(insert_query.as_str())?;
+                if rows_to_insert.len() == rows_per_insert {
+                    full_insert_statement = Some(statement.clone());
+                }
+                statement
+            };
 
-            // If we've reached the minimum number to insert, do it so and reset the insert query
-            if rows_to_insert == min_rows_for_insert || rows_read_in_this_thread == chunk.limit {
-                let column_names_list: String = format!("{:?}", column_names);
-                let column_names_list = column_names_list.replace("[", "(");
-                let column_names_list = column_names_list.replace("]", ")");
-
-                let query = format!(
-                    "INSERT INTO {}.{} {} VALUES {}",
-                    import_config.schema, import_config.table, column_names_list, query_values
-                );
-
-                db_clients.target_client.execute(query.as_str(), &[]).unwrap();
-
-                rows_to_insert = 0;
-                query_values = String::from("");
-            }
+            let params: Vec<&(dyn ToSql + Sync)> = values.iter().map(|value| value as &(dyn ToSql + Sync)).collect();
+            db_clients.target_client.execute(&statement, &params)?;
         }
+
+        Ok(())
     }
 
 }
 
-// Convert any SQL type to a string. Not a extensive list. Just supporting the most common ones.
-fn string_value_for(row: &Row, column: &Column) -> String {
-    // Try to get the value. Will return null in case of error
-    return match &*column.type_() {
-        &Type::BOOL => match row.try_get(column.name()) {
-            Ok(val) => {
-                let val: bool = val;
-                val.to_string()
-            }
-            Err(error) => handle_sql_error(error),
-        },
-        &Type::INT2 => match row.try_get(column.name()) {
-            Ok(val) => {
-                let val: i16 = val;
-                val.to_string()
-            }
-            Err(error) => handle_sql_error(error),
-        },
-        &Type::INT4 => match row.try_get(column.name()) {
-            Ok(val) => {
-                let val: i32 = val;
-                val.to_string()
-            }
-            Err(error) => handle_sql_error(error),
-        },
-        &Type::INT8 => match row.try_get(column.name()) {
-            Ok(val) => {
-                let val: i64 = val;
-                val.to_string()
-            }
-            Err(error) => handle_sql_error(error),
-        },
-        &Type::VARCHAR | &Type::TEXT => match row.try_get(column.name()) {
-            Ok(val) => {
-                let val: String = val;
-                let val: String = val.replace("'", "''");
-                format!("'{}'", val.to_string())
-            }
-            Err(error) => handle_sql_error(error),
-        },
-        &Type::FLOAT4 => match row.try_get(column.name()) {
-            Ok(val) => {
-                let val: f32 = val;
-                val.to_string()
-            }
-            Err(error) => handle_sql_error(error),
-        },
-        &Type::NUMERIC => match row.try_get(column.name()) {
-            Ok(val) => {
-                let val: Decimal = val;
-                val.to_string()
-            }
-            Err(error) => handle_sql_error(error),
-        },
-        &Type::TIMESTAMPTZ => match row.try_get(column.name()) {
-            Ok(timestamptz) => {
-                let value: SystemTime = timestamptz;
-                let datetime: DateTime<Utc> = value.into();
-                format!("'{}'", datetime.format("%Y-%m-%d %T"))
-            }
-            Err(error) => handle_sql_error(error),
-        },
-        unknown => {
-            log_error(
-                format!(
-                    "Error. Postgres Type not supported yet by the pgimporter: {}",
-                    unknown
-                )
-                .as_str(),
-            );
-            "NULL".to_owned()
-        }
+// Large objects are referenced by their oid, sent in binary format as a 4 bytes integer
+fn copy_large_object_for<'a>(import_config: &ImportConfig, source_client: &mut Client, target_client: &mut Client, value: RawValue<'a>)
+    -> Result<RawValue<'a>, postgres::Error> {
+    let source_oid = match &value.0 {
+        Some(raw) => u32::from_be_bytes(raw.as_ref().try_into().unwrap()),
+        None => return Ok(value),
     };
+
+    let target_oid = large_objects::copy_large_object(import_config, source_client, target_client, source_oid)?;
+
+    Ok(RawValue(Some(Cow::Owned(target_oid.to_be_bytes().to_vec()))))
 }
 
-fn handle_sql_error(_error: Error) -> String {
-    // FIXME: Re-enable error logging once we can identify when error comes from reading a NULL value.
-    // Otherwise performance gets very affected
-    //log_error(format!("{}", error).as_str());
-    "NULL".to_owned()
+// Create a multi-row INSERT, with a parameter for every column of every row
+fn insert_query_for(import_config: &ImportConfig, rows: usize) -> String {
+    let columns_per_row = import_config.target_columns.len();
+
+    let mut rows_values: Vec<String> = vec![];
+    for row in 0..rows {
+        // Values read as text are cast to the type of the target column
+        let placeholders: Vec<String> = (1..=columns_per_row)
+            .map(|column| match import_config.text_columns.iter().find(|(text_column, _)| *text_column == column - 1) {
+                Some((_, target_type)) => format!("${}::text::{}", row * columns_per_row + column, target_type),
+                None => format!("${}", row * columns_per_row + column)
+            })
+            .collect();
+        rows_values.push(format!("({})", placeholders.join(", ")));
+    }
+
+    let mut overriding = "";
+    if import_config.overriding_system_value {
+        overriding = "OVERRIDING SYSTEM VALUE";
+    }
+
+    // Values are inserted in the target columns matched by name with the source ones
+    format!(
+        "INSERT INTO {} ({}) {} VALUES {}",
+        sql::qualified_table(&import_config.schema, &import_config.table),
+        sql::quote_idents(&import_config.target_columns),
+        overriding,
+        rows_values.join(", ")
+    )
 }
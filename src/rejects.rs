@@ -0,0 +1,109 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, Ordering};
+use lazy_static::lazy_static;
+use postgres::{Client, Error};
+
+use crate::config::{ImportConfig, CONFIG_PROPERTIES};
+use crate::copy;
+use crate::large_objects;
+use crate::sql;
+
+// Rejected rows are shared between all worker threads, so they're written to the same file
+lazy_static! {
+    static ref REJECT_FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+static REJECTED_ROWS: AtomicI64 = AtomicI64::new(0);
+
+pub fn rejected_rows() -> i64 {
+    REJECTED_ROWS.load(Ordering::SeqCst)
+}
+
+// Write the buffer to the target table. If the COPY fails, the rows in the buffer are split in halves and retried
+// until every failing row is isolated and written to the reject file, so all the good rows are still imported
+pub fn write_isolating_rejects(import_config:&ImportConfig, target_client:&mut Client, buffer:&[u8]) {
+    // In COPY text format every row ends with a new line (new lines inside values are escaped)
+    let rows:Vec<&[u8]> = buffer.split(|byte| *byte == b'\n').filter(|row| !row.is_empty()).collect();
+    let mut rejected_rows = vec!();
+    write_rows(import_config, target_client, &rows, &mut rejected_rows);
+
+    // Large objects were already copied for all the rows, so the ones only referenced by rejected rows would be left orphan
+    if !rejected_rows.is_empty() && !import_config.large_object_columns.is_empty() {
+        let accepted_rows:Vec<&[u8]> = rows.iter().filter(|row| !rejected_rows.iter().any(|rejected_row| std::ptr::eq(*rejected_row, **row)))
+            .copied().collect();
+        large_objects::unlink_large_objects_of(import_config, target_client, &rejected_rows, &accepted_rows);
+    }
+}
+
+fn write_rows<'a>(import_config:&ImportConfig, target_client:&mut Client, rows:&[&'a [u8]], rejected_rows:&mut Vec<&'a [u8]>) {
+    if rows.is_empty() {
+        return;
+    }
+
+    let mut buffer = vec!();
+    for row in rows {
+        buffer.extend_from_slice(row);
+        buffer.push(b'\n');
+    }
+
+    if let Err(error) = try_copy_in(import_config, target_client, &buffer) {
+        if rows.len() == 1 {
+            reject_row(import_config, rows[0], &error);
+            rejected_rows.push(rows[0]);
+        }
+        else {
+            let (first_half, second_half) = rows.split_at(rows.len() / 2);
+            write_rows(import_config, target_client, first_half, rejected_rows);
+            write_rows(import_config, target_client, second_half, rejected_rows);
+        }
+    }
+}
+
+fn try_copy_in(import_config:&ImportConfig, target_client:&mut Client, buffer:&[u8]) -> Result<u64, Error> {
+    // A failed COPY aborts the transaction the target client is in, so each try must be protected by a savepoint
+    if !import_config.in_transaction {
+        return copy::copy_in(import_config, target_client, buffer);
+    }
+
+    target_client.batch_execute("SAVEPOINT pgimporter_rejects").unwrap();
+    let result = copy::copy_in(import_config, target_client, buffer);
+    if result.is_err() {
+        target_client.batch_execute("ROLLBACK TO SAVEPOINT pgimporter_rejects").unwrap();
+    }
+    target_client.batch_execute("RELEASE SAVEPOINT pgimporter_rejects").unwrap();
+
+    result
+}
+
+fn reject_row(import_config:&ImportConfig, row:&[u8], error:&Error) {
+    let error_msg = match error.as_db_error() {
+        Some(db_error) => db_error.message().to_owned(),
+        None => error.to_string()
+    };
+
+    // The rejected row is kept in COPY text format, adding the error as the last column
+    let mut line = row.to_vec();
+    line.push(b'\t');
+    line.extend_from_slice(sql::escape_copy_text(&error_msg).as_bytes());
+    line.push(b'\n');
+
+    {
+        let mut reject_file = REJECT_FILE.lock().unwrap();
+        if reject_file.is_none() {
+            *reject_file = match OpenOptions::new().create(true).append(true).open(&CONFIG_PROPERTIES.reject_filename) {
+                Ok(file) => Some(file),
+                Err(error) => { println!("Couldn't open reject file {} : {}", CONFIG_PROPERTIES.reject_filename, error); std::process::exit(1); }
+            };
+        }
+        reject_file.as_mut().unwrap().write_all(&line).unwrap();
+    }
+
+    let rejected_rows = REJECTED_ROWS.fetch_add(1, Ordering::SeqCst) + 1;
+    if rejected_rows > CONFIG_PROPERTIES.max_rejects {
+        println!("ERROR: More than {} rows rejected. Last one from table {}.{}: {}. Aborting import",
+            CONFIG_PROPERTIES.max_rejects, import_config.schema, import_config.table, error_msg);
+        std::process::exit(1);
+    }
+}
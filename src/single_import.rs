@@ -1,33 +1,51 @@
-use std::io::{BufRead, Write};
+use std::io::BufRead;
 use crate::config::{ImportConfig, CONFIG_PROPERTIES};
+use crate::copy::write_to_target;
+use crate::db;
+use crate::filter;
+use crate::large_objects;
+use crate::sql;
 use postgres::{Client, NoTls};
 use indicatif::{ProgressBar, ProgressStyle};
 
 pub fn single_thread_import(import_config:&ImportConfig, total_rows_to_import:u64) {
-    let max_rows_per_batch = CONFIG_PROPERTIES.rows_select;
+    let mut target_client = match Client::connect(import_config.target_db_url.as_ref(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
+    };
 
+    // In REPLACE mode, matching rows are deleted in the same transaction used to load the new ones
+    if import_config.replace {
+        target_client.batch_execute("BEGIN").unwrap();
+    }
+    import_rows_with(import_config, &mut target_client, total_rows_to_import);
+    if import_config.replace {
+        target_client.batch_execute("COMMIT").unwrap();
+    }
+}
+
+// Import all the rows with the given target client, in the transaction it's in if any
+pub fn import_rows_with(import_config:&ImportConfig, target_client:&mut Client, total_rows_to_import:u64) {
     let mut source_client = match Client::connect(import_config.source_db_url.as_ref(), NoTls) {
         Ok(client) => client,
         Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
     };
-    
-    let mut target_client = match Client::connect(import_config.target_db_url.as_ref(), NoTls) {
-        Ok(client) => client,
-        Err(error) => { println!("Couldn't connect to target DB. Error: {}", error);  std::process::exit(1); }
+    filter::use_keys_tables(&mut source_client);
+
+    // Large objects are read with another connection, so both read the same snapshot
+    let (snapshot_client, snapshot_id) = match import_config.large_object_columns.is_empty() {
+        true => (None, None),
+        false => db::export_source_snapshot(&import_config.source_db_url)
     };
+    db::use_source_snapshot(&mut source_client, &snapshot_id);
+    let mut large_objects_client = large_objects::large_objects_client_for(import_config, &snapshot_id);
+    drop(snapshot_client);
 
-    // Create WHERE section of query (if present)
-    let mut complete_where:String = import_config.where_clause.to_owned().to_string();
-    if !import_config.where_clause.is_empty() {
-        complete_where = format!("WHERE {}", import_config.where_clause);
+    if import_config.replace {
+        filter::use_keys_tables(target_client);
+        delete_from_target(import_config, target_client);
     }
 
-    // Create copy query to extract data
-    let select_query = format!("SELECT * FROM {}.{} {}", import_config.schema, import_config.table, complete_where);
-    let copy_out_query:String = format!("COPY ({}) TO STDOUT", select_query);
-    
-    let mut reader = source_client.copy_out(copy_out_query.as_str()).unwrap();
-
     // Create ProgressBar to show progress of import to user
     let pb = ProgressBar::new(total_rows_to_import);
     let sty = ProgressStyle::default_bar()
@@ -36,6 +54,28 @@ pub fn single_thread_import(import_config:&ImportConfig, total_rows_to_import:u6
     pb.set_style(sty);
     pb.set_position(0);
 
+    let total_rows = copy_rows(import_config, &mut source_client, target_client, &mut large_objects_client, &pb);
+    pb.finish_and_clear();
+
+    println!("TOTAL ROWS READ: {}", total_rows);
+}
+
+// Stream all the rows to import from source to target with a single COPY, writing them in batches. Returns the number of rows read
+// Large objects referenced by the rows are read with the large objects client
+pub fn copy_rows(import_config:&ImportConfig, source_client:&mut Client, target_client:&mut Client, large_objects_client:&mut Option<Client>,
+    pb:&ProgressBar) -> i64 {
+    let max_rows_per_batch = CONFIG_PROPERTIES.rows_select;
+
+    // Create copy query to extract data
+    let mut select_query = format!("SELECT {} FROM {} {}", import_config.source_columns.join(", "),
+        import_config.source_table, sql::where_section(&import_config.source_where_clause));
+    if !import_config.rows_order.is_empty() {
+        select_query = format!("{} ORDER BY {}", select_query, import_config.rows_order);
+    }
+    let copy_out_query:String = format!("COPY ({}) TO STDOUT", select_query);
+    
+    let mut reader = source_client.copy_out(copy_out_query.as_str()).unwrap();
+
     let mut buffer = vec!();
     let mut total_rows = 0;
     // Keep reading from source until reader is empty
@@ -46,8 +86,8 @@ pub fn single_thread_import(import_config:&ImportConfig, total_rows_to_import:u6
         // If we've reached EOF, end now, writing remaining rows on buffer
         if row_bytes == 0 {
             if buffer.len() > 0 {
-                write_to_target(import_config, &mut target_client, &buffer);
-                pb.finish_and_clear();
+                write_buffer(import_config, target_client, large_objects_client, &buffer);
+                pb.set_position(total_rows as u64);
             }
             break;
         }
@@ -56,7 +96,7 @@ pub fn single_thread_import(import_config:&ImportConfig, total_rows_to_import:u6
         total_rows = total_rows + 1;
 
         if total_rows % max_rows_per_batch == 0 {
-            write_to_target(import_config, &mut target_client, &buffer);
+            write_buffer(import_config, target_client, large_objects_client, &buffer);
             pb.set_position(total_rows as u64);
             buffer = vec!();
         }
@@ -65,13 +105,22 @@ pub fn single_thread_import(import_config:&ImportConfig, total_rows_to_import:u6
         reader.consume(row_bytes);
     }
 
-    println!("TOTAL ROWS READ: {}", total_rows);
+    total_rows
 }
 
-fn write_to_target(import_config:&ImportConfig, target_client:&mut Client, buffer:&[u8]) {
-    // Create copy query to import data
-    let copy_in_query:String = format!("COPY {}.{} FROM STDIN", import_config.schema, import_config.table);
-    let mut writer = target_client.copy_in(copy_in_query.as_str()).unwrap();
-    writer.write_all(&buffer).unwrap();
-    writer.finish().unwrap();
+fn write_buffer(import_config:&ImportConfig, target_client:&mut Client, large_objects_client:&mut Option<Client>, buffer:&[u8]) {
+    if let Err(error) = write_to_target(import_config, target_client, large_objects_client, buffer) {
+        println!("ERROR: Couldn't write rows to target table {}.{} | Error: {}", import_config.schema, import_config.table, error);
+        std::process::exit(1);
+    }
+}
+
+fn delete_from_target(import_config:&ImportConfig, target_client:&mut Client) {
+    let delete_query = format!("DELETE FROM {} {}", import_config.target_table,
+        sql::where_section(&import_config.where_clause));
+
+    match target_client.execute(delete_query.as_str(), &[]) {
+        Ok(deleted_rows) => println!("DELETED {} rows from target table {}.{}", deleted_rows, import_config.schema, import_config.table),
+        Err(error) => { println!("Couldn't execute query: {} | Error: {} ", delete_query, error); std::process::exit(1); }
+    }
 }
@@ -0,0 +1,77 @@
+// Helpers to build SQL queries safely from schema, table and column names
+
+// Quote an identifier so it's used exactly as it is (mixed case, reserved words...), like quote_ident() in Postgres
+pub fn quote_ident(name:&str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+pub fn qualified_table(schema:&str, table:&str) -> String {
+    format!("{}.{}", quote_ident(schema), quote_ident(table))
+}
+
+// Only the rows of the table itself, without the ones of the tables inheriting from it
+pub fn only_table(schema:&str, table:&str) -> String {
+    format!("ONLY {}", qualified_table(schema, table))
+}
+
+// Comma separated list of quoted identifiers, e.g. for the column list of an INSERT or COPY
+pub fn quote_idents(names:&[String]) -> String {
+    names.iter().map(|name| quote_ident(name)).collect::<Vec<String>>().join(", ")
+}
+
+// Use the result of a query as a table, e.g. SELECT * FROM (SELECT ...) AS pgimporter_query
+pub fn query_as_table(query:&str) -> String {
+    // A trailing semicolon is fine to run the query on its own, but not inside another query
+    format!("({}) AS pgimporter_query", query.trim().trim_end_matches(';'))
+}
+
+// Quote a value as a string literal, like quote_literal() in Postgres. Postgres casts it to the type of the column it's compared with
+pub fn quote_literal(value:&str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+// Add a condition to a WHERE clause, which may be empty
+pub fn and_where(where_clause:&str, condition:&str) -> String {
+    if where_clause.is_empty() {
+        return condition.to_string();
+    }
+
+    format!("({}) AND {}", where_clause, condition)
+}
+
+// Escape a value written in COPY text format, where backslashes, tabs and new lines have a special meaning
+pub fn escape_copy_text(value:&str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+pub fn where_section(where_clause:&str) -> String {
+    if where_clause.is_empty() {
+        return String::from("");
+    }
+
+    format!("WHERE {}", where_clause)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_ident_keeps_the_name_as_it_is() {
+        assert_eq!(quote_ident("orders"), "\"orders\"");
+        assert_eq!(quote_ident("Order Lines"), "\"Order Lines\"");
+        assert_eq!(quote_ident("user"), "\"user\"");
+    }
+
+    #[test]
+    fn quote_ident_escapes_double_quotes() {
+        assert_eq!(quote_ident("my\"table"), "\"my\"\"table\"");
+        assert_eq!(quote_ident("\""), "\"\"\"\"");
+    }
+
+    #[test]
+    fn quoted_tables_and_column_lists() {
+        assert_eq!(qualified_table("Sales", "order\"s"), "\"Sales\".\"order\"\"s\"");
+        assert_eq!(quote_idents(&[String::from("id"), String::from("Name")]), "\"id\", \"Name\"");
+    }
+}
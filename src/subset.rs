@@ -0,0 +1,226 @@
+use postgres::{Client, NoTls};
+
+use crate::config;
+use crate::config::ImportOptions;
+use crate::filter;
+use crate::sql;
+use crate::versions;
+
+// Foreign key in the source DB, with its columns in the same order in both tables
+struct ForeignKey {
+    child:(String, String),
+    child_columns:Vec<String>,
+    parent:(String, String),
+    parent_columns:Vec<String>
+}
+
+// Table of the subset, with the temporary table of the source DB keeping the keys of its chosen rows. Rows chosen as parents
+// of other rows don't follow their children, which are only followed down from roots and from other children
+struct SubsetTable {
+    table:(String, String),
+    // Quoted columns identifying each row of the table, and the key they make as text
+    key_columns:String,
+    key:String,
+    rows_table:String
+}
+
+// Tables and rows to import so the subset defined by the roots (tables with a WHERE clause) has no dangling references:
+// the parents required by its rows (through their foreign keys, recursively), and optionally the rows of the given child
+// tables referencing them (at most limit rows for each foreign key, the first ones by their key). The rows are chosen once
+// in the source DB, following the foreign keys again until no more rows are added, so foreign key cycles are followed
+// as many times as needed. Each table is imported with a WHERE clause that selects the keys of its chosen rows
+pub fn subset_tables(roots:&[((String, String), String)], children:&[((String, String), Option<i64>)], options:&ImportOptions)
+    -> Vec<(String, String, ImportOptions)> {
+    let mut source_client = match Client::connect(config::get_source_db_url().as_str(), NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to source DB. Error: {}", error);  std::process::exit(1); }
+    };
+    let foreign_keys = get_foreign_keys(&mut source_client);
+    let mut subset:Vec<SubsetTable> = vec!();
+
+    for (table, where_clause) in roots {
+        let root = subset_table_for(&mut source_client, &mut subset, table);
+        let root_rows_query = format!("INSERT INTO {} AS pgimporter_subset SELECT {}, true FROM {} WHERE {} \
+            ON CONFLICT (key) DO UPDATE SET follow_children = true WHERE NOT pgimporter_subset.follow_children",
+            subset[root].rows_table, subset[root].key, sql::qualified_table(&table.0, &table.1), where_clause);
+        if let Err(error) = source_client.execute(root_rows_query.as_str(), &[]) {
+            println!("ERROR: Couldn't select the rows of root table {}.{} of the subset: {} | Error: {}", table.0, table.1, where_clause, error);
+            std::process::exit(1);
+        }
+    }
+
+    // Rows of a child table added through each foreign key, for the limit of the child table
+    let mut child_rows:Vec<i64> = vec![0; foreign_keys.len()];
+    loop {
+        let mut added_rows = 0;
+        for (i, foreign_key) in foreign_keys.iter().enumerate() {
+            // Parents referenced by the rows of the subset
+            if let Some(child) = subset.iter().position(|subset_table| subset_table.table == foreign_key.child) {
+                let parent = subset_table_for(&mut source_client, &mut subset, &foreign_key.parent);
+                added_rows += add_rows(&mut source_client, &parent_rows_query(foreign_key, &subset[child], &subset[parent]));
+            }
+
+            // Rows of the chosen children referencing the rows of the subset (a table referencing itself isn't its own child)
+            if foreign_key.child == foreign_key.parent {
+                continue;
+            }
+            let parent = subset.iter().position(|subset_table| subset_table.table == foreign_key.parent);
+            let child_limit = children.iter().find(|(child, _)| child == &foreign_key.child).map(|(_, limit)| limit);
+            if let (Some(parent), Some(limit)) = (parent, child_limit) {
+                let remaining_rows = limit.map(|limit| limit - child_rows[i]);
+                if remaining_rows.is_some_and(|remaining_rows| remaining_rows <= 0) {
+                    continue;
+                }
+                let child = subset_table_for(&mut source_client, &mut subset, &foreign_key.child);
+                let rows = add_rows(&mut source_client, &child_rows_query(foreign_key, &subset[parent], &subset[child], remaining_rows));
+                child_rows[i] += rows;
+                added_rows += rows;
+            }
+        }
+
+        if added_rows == 0 {
+            break;
+        }
+    }
+
+    for (child, _) in children {
+        if !subset.iter().any(|subset_table| &subset_table.table == child) {
+            println!("WARNING: Table {}.{} doesn't reference any table of the subset. Skipping it", child.0, child.1);
+        }
+    }
+
+    let mut tables = vec!();
+    for subset_table in &subset {
+        let (schema, table) = &subset_table.table;
+        let keys:Vec<String> = source_client.query(format!("SELECT key FROM {} ORDER BY key", subset_table.rows_table).as_str(), &[]).unwrap()
+            .iter().map(|row| row.get(0)).collect();
+        let keys_table = filter::keys_table_for(&format!("subset table {}.{}", schema, table), keys);
+        let where_clause = format!("ROW({})::text IN (SELECT key FROM pg_temp.{})", subset_table.key_columns, keys_table);
+        tables.push((schema.to_owned(), table.to_owned(), ImportOptions { where_clause, ..options.clone() }));
+    }
+
+    // The same WHERE clauses the tables are imported with, reading the keys from the same temporary tables
+    println!("Subset of {} tables:", tables.len());
+    filter::use_keys_tables(&mut source_client);
+    for (schema, table, table_options) in &tables {
+        let count_query = format!("SELECT count(1) FROM {} WHERE {}", sql::qualified_table(schema, table), table_options.where_clause);
+        match source_client.query_one(count_query.as_str(), &[]) {
+            Ok(row) => { let rows:i64 = row.get(0); println!("  {}.{} - {} rows", schema, table, rows); },
+            Err(error) => {
+                println!("ERROR: Couldn't select the rows of {}.{} in the subset: {} | Error: {}", schema, table, table_options.where_clause, error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    tables
+}
+
+// Position of the table in the subset, adding it (without any row) the first time it's reached
+fn subset_table_for(source_client:&mut Client, subset:&mut Vec<SubsetTable>, table:&(String, String)) -> usize {
+    if let Some(position) = subset.iter().position(|subset_table| &subset_table.table == table) {
+        return position;
+    }
+
+    let key_columns = sql::quote_idents(&key_columns_of(source_client, table));
+    let rows_table = format!("pg_temp.pgimporter_subset_{}", subset.len());
+    let create_table = format!("DROP TABLE IF EXISTS {}; CREATE TEMP TABLE {} (key text PRIMARY KEY, follow_children bool)", rows_table, rows_table);
+    if let Err(error) = source_client.batch_execute(&create_table) {
+        println!("ERROR: Couldn't create temporary table {} for the rows of {}.{} in the subset | Error: {}", rows_table, table.0, table.1, error);
+        std::process::exit(1);
+    }
+
+    subset.push(SubsetTable { table: table.to_owned(), key: format!("ROW({})::text", key_columns), key_columns, rows_table });
+    subset.len() - 1
+}
+
+// Columns identifying each row of the table: its primary key, or a unique constraint without NULL values.
+// Rows of tables without any of them are identified by all their columns
+fn key_columns_of(source_client:&mut Client, table:&(String, String)) -> Vec<String> {
+    let key_columns_query = "SELECT coalesce(
+            (SELECT ARRAY(SELECT pga.attname::text FROM unnest(pgco.conkey) WITH ORDINALITY AS key_column(attnum, i)
+                    JOIN pg_attribute pga ON pga.attrelid = pgco.conrelid AND pga.attnum = key_column.attnum ORDER BY key_column.i)
+                FROM pg_constraint pgco
+                WHERE pgco.conrelid = $1::text::regclass AND pgco.contype IN ('p', 'u')
+                    AND NOT EXISTS (SELECT FROM pg_attribute pga WHERE pga.attrelid = pgco.conrelid AND pga.attnum = ANY (pgco.conkey) AND NOT pga.attnotnull)
+                ORDER BY pgco.contype, pgco.conname LIMIT 1),
+            ARRAY(SELECT attname::text FROM pg_attribute WHERE attrelid = $1::text::regclass AND attnum > 0 AND NOT attisdropped ORDER BY attnum))";
+
+    match source_client.query_one(key_columns_query, &[&sql::qualified_table(&table.0, &table.1)]) {
+        Ok(row) => row.get(0),
+        Err(error) => {
+            println!("ERROR: Table {}.{} of the subset doesn't exist in source DB | Error: {}", table.0, table.1, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Run a query adding rows to a table of the subset, returning the number of rows added (or now following their children)
+fn add_rows(source_client:&mut Client, add_rows_query:&str) -> i64 {
+    match source_client.execute(add_rows_query, &[]) {
+        Ok(rows) => rows as i64,
+        Err(error) => { println!("ERROR: Couldn't select the rows of the subset: {} | Error: {}", add_rows_query, error); std::process::exit(1); }
+    }
+}
+
+// Add the parents referenced by the rows of the child table. In a table referencing itself (e.g. the managers of some employees)
+// all their ancestors are added at once
+fn parent_rows_query(foreign_key:&ForeignKey, child:&SubsetTable, parent:&SubsetTable) -> String {
+    let child_table = sql::qualified_table(&foreign_key.child.0, &foreign_key.child.1);
+    let mut referenced_keys = format!("SELECT {} FROM {} WHERE {} IN (SELECT key FROM {})", sql::quote_idents(&foreign_key.child_columns),
+        child_table, child.key, child.rows_table);
+
+    if foreign_key.child == foreign_key.parent {
+        let ancestor_keys:Vec<String> = (1..=foreign_key.child_columns.len()).map(|i| format!("pgimporter_key_{}", i)).collect();
+        let row_columns:Vec<String> = foreign_key.child_columns.iter().map(|column| format!("pgimporter_row.{}", sql::quote_ident(column))).collect();
+        let join_ancestors:Vec<String> = foreign_key.parent_columns.iter().zip(&ancestor_keys)
+            .map(|(column, ancestor_key)| format!("pgimporter_row.{} = pgimporter_ancestors.{}", sql::quote_ident(column), ancestor_key))
+            .collect();
+        referenced_keys = format!("WITH RECURSIVE pgimporter_ancestors ({}) AS ({} \
+            UNION SELECT {} FROM {} AS pgimporter_row JOIN pgimporter_ancestors ON {}) SELECT {} FROM pgimporter_ancestors",
+            ancestor_keys.join(", "), referenced_keys, row_columns.join(", "), child_table, join_ancestors.join(" AND "), ancestor_keys.join(", "));
+    }
+
+    format!("INSERT INTO {} SELECT {}, false FROM {} WHERE ({}) IN ({}) ON CONFLICT (key) DO NOTHING", parent.rows_table, parent.key,
+        sql::qualified_table(&foreign_key.parent.0, &foreign_key.parent.1), sql::quote_idents(&foreign_key.parent_columns), referenced_keys)
+}
+
+// Add the rows of the child table referencing the rows of the parent table that follow their children, up to the remaining rows
+// of the limit of the child table
+fn child_rows_query(foreign_key:&ForeignKey, parent:&SubsetTable, child:&SubsetTable, remaining_rows:Option<i64>) -> String {
+    let mut child_rows = format!("SELECT {} AS pgimporter_key, true AS pgimporter_follow_children FROM {} WHERE ({}) IN (SELECT {} FROM {} WHERE {} IN (SELECT key FROM {} WHERE follow_children)) \
+        AND {} NOT IN (SELECT key FROM {} WHERE follow_children)", child.key, sql::qualified_table(&foreign_key.child.0, &foreign_key.child.1),
+        sql::quote_idents(&foreign_key.child_columns), sql::quote_idents(&foreign_key.parent_columns),
+        sql::qualified_table(&foreign_key.parent.0, &foreign_key.parent.1), parent.key, parent.rows_table, child.key, child.rows_table);
+    if let Some(remaining_rows) = remaining_rows {
+        child_rows = format!("{} ORDER BY {} LIMIT {}", child_rows, child.key_columns, remaining_rows);
+    }
+
+    format!("INSERT INTO {} AS pgimporter_subset {} ON CONFLICT (key) DO UPDATE SET follow_children = true WHERE NOT pgimporter_subset.follow_children",
+        child.rows_table, child_rows)
+}
+
+fn get_foreign_keys(source_client:&mut Client) -> Vec<ForeignKey> {
+    // Keys of partitioned tables are copied to their partitions, which are read along with their parent table
+    let mut not_partitions = "";
+    if versions::source_supports(versions::PARTITIONS_VERSION) {
+        not_partitions = "AND NOT child.relispartition AND NOT parent.relispartition";
+    }
+    let foreign_keys_query = format!("SELECT child_ns.nspname::text, child.relname::text, parent_ns.nspname::text, parent.relname::text,
+            ARRAY(SELECT pga.attname::text FROM generate_subscripts(pgco.conkey, 1) i
+                JOIN pg_attribute pga ON pga.attrelid = pgco.conrelid AND pga.attnum = pgco.conkey[i] ORDER BY i),
+            ARRAY(SELECT pga.attname::text FROM generate_subscripts(pgco.confkey, 1) i
+                JOIN pg_attribute pga ON pga.attrelid = pgco.confrelid AND pga.attnum = pgco.confkey[i] ORDER BY i)
+        FROM pg_constraint pgco
+        JOIN pg_class child ON child.oid = pgco.conrelid
+        JOIN pg_namespace child_ns ON child_ns.oid = child.relnamespace
+        JOIN pg_class parent ON parent.oid = pgco.confrelid
+        JOIN pg_namespace parent_ns ON parent_ns.oid = parent.relnamespace
+        WHERE pgco.contype = 'f' {}
+        ORDER BY pgco.conname", not_partitions);
+
+    source_client.query(foreign_keys_query.as_str(), &[]).unwrap().iter()
+        .map(|row| ForeignKey { child: (row.get(0), row.get(1)), parent: (row.get(2), row.get(3)), child_columns: row.get(4),
+            parent_columns: row.get(5) })
+        .collect()
+}
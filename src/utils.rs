@@ -3,7 +3,11 @@ use std::time::Duration;
 use resolve::resolve_host;
 use log::{error};
 
-use crate::config::{ CONFIG_PROPERTIES };
+use crate::check;
+use crate::config;
+use crate::config::{ CONFIG_PROPERTIES, SESSION_SETTINGS };
+use crate::db;
+use crate::versions;
 
 pub fn check_postgres_source_target_servers() -> bool {
     let source_db_connection = &CONFIG_PROPERTIES.source;
@@ -11,7 +15,64 @@ pub fn check_postgres_source_target_servers() -> bool {
     let target_db_connection = &CONFIG_PROPERTIES.target;
 
     check_postgres_server("Source DB", source_db_connection.host.as_str(), source_db_connection.port.as_str()) && 
-    check_postgres_server("Target DB", target_db_connection.host.as_str(), target_db_connection.port.as_str())
+    check_postgres_server("Target DB", target_db_connection.host.as_str(), target_db_connection.port.as_str()) &&
+    check_server_settings() &&
+    versions::check_server_versions()
+}
+
+// Values are moved between servers in text format, so both sessions must write and read them in the same way.
+// Must be checked again when using other databases, as each database has its own encoding
+pub fn check_server_settings() -> bool {
+    let source_settings = db::get_server_settings(config::get_source_db_url_with_server_settings().as_str());
+    let target_settings = db::get_server_settings(config::get_target_db_url_with_server_settings().as_str());
+
+    for (name, value) in SESSION_SETTINGS.iter() {
+        let source_value = source_settings.get(*name);
+        let target_value = target_settings.get(*name);
+        if source_value != target_value {
+            println!("INFO: {} is different in source ({}) and target ({}) DBs. Using {} in both of them", name,
+                source_value.map_or("?", |v| v.as_str()), target_value.map_or("?", |v| v.as_str()), value);
+        }
+    }
+
+    let source_encoding = source_settings.get("server_encoding").map_or("?", |v| v.as_str());
+    let target_encoding = target_settings.get("server_encoding").map_or("?", |v| v.as_str());
+
+    if source_encoding == "SQL_ASCII" && target_encoding != "SQL_ASCII" {
+        println!("WARNING: Source DB encoding is SQL_ASCII, so its characters can't be checked. Rows with invalid characters for {} will fail", 
+            target_encoding);
+    }
+    // Any encoding can be converted to UTF8, but not the other way around
+    else if source_encoding != target_encoding && target_encoding != "UTF8" {
+        // The rows may only use characters that exist in both encodings (e.g. LATIN1 into LATIN9), which only the user can tell
+        if !CONFIG_PROPERTIES.check_encoding {
+            println!("WARNING: Source DB encoding {} has characters that don't exist in target DB encoding {}. Rows with them can't be imported",
+                source_encoding, target_encoding);
+            return true;
+        }
+        println!("ERROR: Values can't be converted without losing data from source DB encoding {} to target DB encoding {}. Use --check-encoding false if the rows only have characters of both encodings", 
+            source_encoding, target_encoding);
+        return false;
+    }
+
+    true
+}
+
+// A dry run only lists what would be created, once everything has been checked
+pub fn exit_if_dry_run() {
+    // The exit code tells scripts running a check whether the tables can be imported
+    if config::is_check_command() {
+        if check::incompatible_tables_found() {
+            println!("ERROR: Check finished. Some tables can't be imported as they are");
+            std::process::exit(1);
+        }
+        println!("INFO: Check finished. All tables can be imported");
+        std::process::exit(0);
+    }
+    if CONFIG_PROPERTIES.dry_run {
+        println!("INFO: Dry run finished. Nothing was created or imported");
+        std::process::exit(0);
+    }
 }
 
 pub fn log_error(err_msg:&str){
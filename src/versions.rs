@@ -0,0 +1,69 @@
+use lazy_static::lazy_static;
+
+use crate::config;
+use crate::db;
+
+// string_agg, needed to read the unique constraints of the tables, exists since 9.0
+pub const MIN_SERVER_VERSION:i32 = 90000;
+
+pub const PARTITIONS_VERSION:i32 = 100000;
+pub const IDENTITY_COLUMNS_VERSION:i32 = 100000;
+pub const GENERATED_COLUMNS_VERSION:i32 = 120000;
+pub const SNAPSHOT_EXPORT_VERSION:i32 = 90200;
+pub const TABLESAMPLE_VERSION:i32 = 90500;
+// pg_sequence catalog and sequences with a data type (AS bigint)
+pub const SEQUENCE_CATALOG_VERSION:i32 = 100000;
+
+// Features used by the importer that depend on the version of the servers
+const FEATURES:[(&str, i32); 5] = [("Partitioned tables", PARTITIONS_VERSION), ("Identity columns", IDENTITY_COLUMNS_VERSION),
+    ("Generated columns", GENERATED_COLUMNS_VERSION), ("Snapshot export", SNAPSHOT_EXPORT_VERSION), ("Table sampling", TABLESAMPLE_VERSION)];
+
+// server_version_num of both servers, read only once
+lazy_static! {
+    pub static ref SOURCE_VERSION: i32 = db::get_server_version_num(config::get_source_db_url().as_str());
+    pub static ref TARGET_VERSION: i32 = db::get_server_version_num(config::get_target_db_url().as_str());
+}
+
+pub fn source_supports(feature_version:i32) -> bool {
+    *SOURCE_VERSION >= feature_version
+}
+
+pub fn target_supports(feature_version:i32) -> bool {
+    *TARGET_VERSION >= feature_version
+}
+
+// Print which features can be used in each server, failing if any of them is too old
+pub fn check_server_versions() -> bool {
+    println!();
+    println!("{:<20} {:>10} {:>10}", "PostgreSQL version", "Source", "Target");
+    println!("{:<20} {:>10} {:>10}", "", version_to_string(*SOURCE_VERSION), version_to_string(*TARGET_VERSION));
+    for (feature, feature_version) in FEATURES.iter() {
+        println!("{:<20} {:>10} {:>10}", feature, yes_or_no(source_supports(*feature_version)), yes_or_no(target_supports(*feature_version)));
+    }
+    println!();
+
+    if *SOURCE_VERSION < MIN_SERVER_VERSION || *TARGET_VERSION < MIN_SERVER_VERSION {
+        println!("ERROR: PostgreSQL servers older than {} aren't supported", version_to_string(MIN_SERVER_VERSION));
+        return false;
+    }
+
+    if *TARGET_VERSION / 10000 < *SOURCE_VERSION / 10000 {
+        println!("WARNING: Target DB is older than source DB. Columns with types or features that only exist in the source version will fail");
+    }
+
+    true
+}
+
+// e.g. 90624 => 9.6.24 | 150002 => 15.2
+fn version_to_string(version:i32) -> String {
+    if version >= 100000 {
+        format!("{}.{}", version / 10000, version % 10000)
+    }
+    else {
+        format!("{}.{}.{}", version / 10000, (version / 100) % 100, version % 100)
+    }
+}
+
+fn yes_or_no(supported:bool) -> &'static str {
+    if supported { "YES" } else { "NO" }
+}
@@ -54,3 +54,152 @@ fn check_inserted_data(){
         assert_eq!(source_results[index].a_number, target_results[index].a_number);
     }
 }
+
+const SOURCE_ARG: &str = "postgres:postgres@postgres-source:5432/postgres";
+const TARGET_ARG: &str = "postgres:postgres@postgres-target:5432/postgres";
+
+fn connect(url:&str) -> Client {
+    match Client::connect(url, NoTls) {
+        Ok(client) => client,
+        Err(error) => { println!("Couldn't connect to DB. Error: {}", error);  std::process::exit(1); }
+    }
+}
+
+// Run the importer with a batch file, returning whether it succeeded
+fn run_batch(name:&str, batch:&str) -> bool {
+    let batch_filename = std::env::temp_dir().join(format!("{}.yml", name));
+    std::fs::write(&batch_filename, batch).unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_pgimporter"))
+        .args(["-s", SOURCE_ARG, "-t", TARGET_ARG, "--batch-filename", batch_filename.to_str().unwrap()])
+        .stdin(std::process::Stdio::null())
+        .status()
+        .unwrap();
+
+    status.success()
+}
+
+#[test]
+fn failed_import_of_a_foreign_key_cycle_keeps_its_rows_and_keys(){
+    let mut source_client = connect(SOURCE_URL);
+    let mut target_client = connect(TARGET_URL);
+
+    let create_tables = "DROP SCHEMA IF EXISTS fk_cycle CASCADE; CREATE SCHEMA fk_cycle;
+        CREATE TABLE fk_cycle.a (id int PRIMARY KEY, b_id int);
+        CREATE TABLE fk_cycle.b (id int PRIMARY KEY, a_id int REFERENCES fk_cycle.a (id));
+        ALTER TABLE fk_cycle.a ADD CONSTRAINT a_b_id_fkey FOREIGN KEY (b_id) REFERENCES fk_cycle.b (id);";
+    source_client.batch_execute(create_tables).unwrap();
+    target_client.batch_execute(create_tables).unwrap();
+    source_client.batch_execute("INSERT INTO fk_cycle.a SELECT id, NULL FROM generate_series(1, 100) id;
+        INSERT INTO fk_cycle.b SELECT id, id FROM generate_series(1, 100) id;
+        UPDATE fk_cycle.a SET b_id = id;").unwrap();
+    // The last rows of the cycle can't be written to the target table, which already has some rows
+    target_client.batch_execute("ALTER TABLE fk_cycle.b ADD CONSTRAINT b_id_check CHECK (id < 50);
+        INSERT INTO fk_cycle.a VALUES (1000, NULL);
+        INSERT INTO fk_cycle.b VALUES (1, 1000);").unwrap();
+
+    let imported = run_batch("fk_cycle", "imports:
+    - schema: fk_cycle
+      tables:
+        - a
+        - b
+      truncate: true
+");
+    assert!(!imported);
+
+    let foreign_keys:i64 = target_client.query_one("SELECT count(*) FROM pg_constraint WHERE contype = 'f' AND connamespace = 'fk_cycle'::regnamespace",
+        &[]).unwrap().get(0);
+    let rows_of_a:i64 = target_client.query_one("SELECT count(*) FROM fk_cycle.a WHERE id = 1000", &[]).unwrap().get(0);
+    let rows_of_b:i64 = target_client.query_one("SELECT count(*) FROM fk_cycle.b WHERE a_id = 1000", &[]).unwrap().get(0);
+    assert_eq!(foreign_keys, 2);
+    assert_eq!(rows_of_a, 1);
+    assert_eq!(rows_of_b, 1);
+}
+
+#[test]
+fn sampled_self_referencing_table_is_imported(){
+    let mut source_client = connect(SOURCE_URL);
+    let mut target_client = connect(TARGET_URL);
+
+    let create_tables = "DROP SCHEMA IF EXISTS fk_sample CASCADE; CREATE SCHEMA fk_sample;
+        CREATE TABLE fk_sample.employees (id int PRIMARY KEY, manager_id int REFERENCES fk_sample.employees (id));";
+    source_client.batch_execute(create_tables).unwrap();
+    target_client.batch_execute(create_tables).unwrap();
+    // Employees are stored before their managers
+    source_client.batch_execute("INSERT INTO fk_sample.employees SELECT id, NULL FROM generate_series(1, 100) id;
+        UPDATE fk_sample.employees SET manager_id = id + 1 WHERE id < 100;").unwrap();
+
+    let imported = run_batch("fk_sample", "imports:
+    - schema: fk_sample
+      tables:
+        - name: employees
+          sample_percent: 100
+      truncate: true
+");
+    assert!(imported);
+
+    let employees:i64 = target_client.query_one("SELECT count(*) FROM fk_sample.employees WHERE manager_id IS NOT NULL", &[]).unwrap().get(0);
+    assert_eq!(employees, 99);
+}
+
+#[test]
+fn subset_follows_foreign_key_cycles_until_complete(){
+    let mut source_client = connect(SOURCE_URL);
+    let mut target_client = connect(TARGET_URL);
+
+    let create_tables = "DROP SCHEMA IF EXISTS subset_cycle CASCADE; CREATE SCHEMA subset_cycle;
+        CREATE TABLE subset_cycle.a (id int PRIMARY KEY, b_id int);
+        CREATE TABLE subset_cycle.b (id int PRIMARY KEY, a_id int REFERENCES subset_cycle.a (id));
+        ALTER TABLE subset_cycle.a ADD CONSTRAINT a_b_id_fkey FOREIGN KEY (b_id) REFERENCES subset_cycle.b (id);";
+    source_client.batch_execute(create_tables).unwrap();
+    target_client.batch_execute(create_tables).unwrap();
+    // Each row of a references a row of b, which references the next row of a
+    source_client.batch_execute("INSERT INTO subset_cycle.a SELECT id, NULL FROM generate_series(1, 20) id;
+        INSERT INTO subset_cycle.b SELECT id, id + 1 FROM generate_series(1, 9) id;
+        UPDATE subset_cycle.a SET b_id = id WHERE id < 10;").unwrap();
+
+    let imported = run_batch("subset_cycle", "imports:
+    - schema: subset_cycle
+      truncate: true
+      subset:
+        roots:
+          - table: a
+            where_clause: id = 1
+");
+    assert!(imported);
+
+    let rows_of_a:i64 = target_client.query_one("SELECT count(*) FROM subset_cycle.a", &[]).unwrap().get(0);
+    let rows_of_b:i64 = target_client.query_one("SELECT count(*) FROM subset_cycle.b", &[]).unwrap().get(0);
+    assert_eq!(rows_of_a, 10);
+    assert_eq!(rows_of_b, 9);
+}
+
+#[test]
+fn tables_without_the_time_column_are_imported_in_full(){
+    let mut source_client = connect(SOURCE_URL);
+    let mut target_client = connect(TARGET_URL);
+
+    let create_tables = "DROP SCHEMA IF EXISTS time_window CASCADE; CREATE SCHEMA time_window;
+        CREATE TABLE time_window.events (created_at timestamptz, payload text);
+        CREATE TABLE time_window.plain (payload text);";
+    source_client.batch_execute(create_tables).unwrap();
+    target_client.batch_execute(create_tables).unwrap();
+    source_client.batch_execute("INSERT INTO time_window.events SELECT now() - id * interval '1 day', 'event' FROM generate_series(1, 30) id;
+        INSERT INTO time_window.plain SELECT 'row' FROM generate_series(1, 30) id;").unwrap();
+
+    let imported = run_batch("time_window", "imports:
+    - schema: time_window
+      tables:
+        - events
+        - plain
+      truncate: true
+      time_column: created_at
+      since: 7d
+");
+    assert!(imported);
+
+    let events:i64 = target_client.query_one("SELECT count(*) FROM time_window.events", &[]).unwrap().get(0);
+    let plain_rows:i64 = target_client.query_one("SELECT count(*) FROM time_window.plain", &[]).unwrap().get(0);
+    assert_eq!(events, 6);
+    assert_eq!(plain_rows, 30);
+}